@@ -0,0 +1,25 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// 静态 API key，明文只在创建时返回一次
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize)]
+#[sea_orm(table_name = "api_key")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub name: String,
+    /// key 的前几位，用于辨认
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    /// 逗号分隔的角色
+    pub roles: String,
+    pub tenant: Option<String>,
+    pub created_at: DateTime,
+    pub revoked_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
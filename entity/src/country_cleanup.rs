@@ -0,0 +1,17 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// 待清理的国家，引用计数归零时标记，重新被引用时移除
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize)]
+#[sea_orm(table_name = "country_cleanup")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub country_id: i64,
+    pub event_id: i64,
+    pub marked_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
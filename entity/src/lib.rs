@@ -1,3 +1,6 @@
 mod prepare;
 pub mod country;
+pub mod ref_count_event;
+pub mod country_cleanup;
+pub mod api_key;
 pub use prepare::*;
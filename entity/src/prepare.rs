@@ -1,4 +1,19 @@
 pub use super::country::ActiveModel as CountryActiveModel;
 pub use super::country::Column as CountryColumn;
 pub use super::country::Entity as Country;
-pub use super::country::Model as CountryModel;
\ No newline at end of file
+pub use super::country::Model as CountryModel;
+
+pub use super::ref_count_event::ActiveModel as RefCountEventActiveModel;
+pub use super::ref_count_event::Column as RefCountEventColumn;
+pub use super::ref_count_event::Entity as RefCountEvent;
+pub use super::ref_count_event::Model as RefCountEventModel;
+
+pub use super::country_cleanup::ActiveModel as CountryCleanupActiveModel;
+pub use super::country_cleanup::Column as CountryCleanupColumn;
+pub use super::country_cleanup::Entity as CountryCleanup;
+pub use super::country_cleanup::Model as CountryCleanupModel;
+
+pub use super::api_key::ActiveModel as ApiKeyActiveModel;
+pub use super::api_key::Column as ApiKeyColumn;
+pub use super::api_key::Entity as ApiKey;
+pub use super::api_key::Model as ApiKeyModel;
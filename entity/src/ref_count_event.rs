@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// 引用计数归零记录，国家被删除后仍然保留
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize)]
+#[sea_orm(table_name = "ref_count_event")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub country_id: i64,
+    /// 归零前的引用计数
+    pub previous: i64,
+    /// 请求减少的数量
+    pub amount: i64,
+    pub forced: bool,
+    /// 归零后执行的动作
+    pub action: String,
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,83 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum ApiKey {
+    Table,
+    Id,
+    Name,
+    Prefix,
+    KeyHash,
+    Roles,
+    Tenant,
+    CreatedAt,
+    RevokedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ApiKey::Table)
+                    .comment("静态 API key，只保存哈希")
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ApiKey::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ApiKey::Name)
+                            .string_len(100)
+                            .not_null()
+                            .comment("用途说明"),
+                    )
+                    .col(
+                        ColumnDef::new(ApiKey::Prefix)
+                            .string_len(16)
+                            .not_null()
+                            .comment("key 的前几位，用于辨认"),
+                    )
+                    .col(
+                        ColumnDef::new(ApiKey::KeyHash)
+                            .char_len(64)
+                            .not_null()
+                            .unique_key()
+                            .comment("key 的 SHA-256，十六进制"),
+                    )
+                    .col(
+                        ColumnDef::new(ApiKey::Roles)
+                            .string_len(200)
+                            .not_null()
+                            .comment("逗号分隔的角色"),
+                    )
+                    .col(ColumnDef::new(ApiKey::Tenant).string_len(100).null())
+                    .col(
+                        ColumnDef::new(ApiKey::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(ApiKey::RevokedAt)
+                            .timestamp()
+                            .null()
+                            .comment("吊销时间，非空时 key 失效"),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().if_exists().table(ApiKey::Table).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,90 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum IdempotencyKey {
+    Table,
+    Principal,
+    Key,
+    RequestHash,
+    Status,
+    Headers,
+    Body,
+    CreatedAt,
+    ExpiresAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(IdempotencyKey::Table)
+                    .comment("带 Idempotency-Key 的请求的首次响应，与修改在同一事务内写入")
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(IdempotencyKey::Principal)
+                            .string_len(255)
+                            .not_null()
+                            .comment("认证方式与调用方"),
+                    )
+                    .col(ColumnDef::new(IdempotencyKey::Key).string_len(255).not_null())
+                    .col(
+                        ColumnDef::new(IdempotencyKey::RequestHash)
+                            .char_len(64)
+                            .not_null()
+                            .comment("方法、路径与请求体的 SHA-256，同一 key 用于不同请求时拒绝"),
+                    )
+                    .col(ColumnDef::new(IdempotencyKey::Status).small_integer().not_null())
+                    .col(
+                        ColumnDef::new(IdempotencyKey::Headers)
+                            .json_binary()
+                            .not_null()
+                            .comment("重放时需要的响应头"),
+                    )
+                    .col(ColumnDef::new(IdempotencyKey::Body).binary().not_null())
+                    .col(
+                        ColumnDef::new(IdempotencyKey::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(IdempotencyKey::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .comment("过期后可以重新使用并删除"),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(IdempotencyKey::Principal)
+                            .col(IdempotencyKey::Key),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .table(IdempotencyKey::Table)
+                    .name("idx-idempotency_key-expires_at")
+                    .col(IdempotencyKey::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .if_exists()
+                    .table(IdempotencyKey::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
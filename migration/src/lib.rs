@@ -1,6 +1,10 @@
 pub use sea_orm_migration::prelude::*;
 
 mod country_table;
+mod ref_count_event;
+mod rate_limit_bucket;
+mod api_key;
+mod idempotency_key;
 
 pub struct Migrator;
 
@@ -9,6 +13,10 @@ impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
         vec![
             Box::new(country_table::Migration),
+            Box::new(ref_count_event::Migration),
+            Box::new(rate_limit_bucket::Migration),
+            Box::new(api_key::Migration),
+            Box::new(idempotency_key::Migration),
         ]
     }
 }
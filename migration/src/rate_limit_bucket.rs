@@ -0,0 +1,81 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum RateLimitBucket {
+    Table,
+    Key,
+    Tokens,
+    Allowed,
+    UpdatedAt,
+    FullAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RateLimitBucket::Table)
+                    .comment("多实例共享的限流令牌桶")
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RateLimitBucket::Key)
+                            .string_len(255)
+                            .not_null()
+                            .primary_key()
+                            .comment("路由与调用方"),
+                    )
+                    .col(
+                        ColumnDef::new(RateLimitBucket::Tokens)
+                            .double()
+                            .not_null()
+                            .comment("剩余令牌"),
+                    )
+                    .col(
+                        ColumnDef::new(RateLimitBucket::Allowed)
+                            .boolean()
+                            .not_null()
+                            .comment("最近一次请求是否放行"),
+                    )
+                    .col(
+                        ColumnDef::new(RateLimitBucket::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .comment("更新时间"),
+                    )
+                    .col(
+                        ColumnDef::new(RateLimitBucket::FullAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .comment("令牌补满的时间，之后可以删除"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .table(RateLimitBucket::Table)
+                    .name("idx-rate_limit_bucket-full_at")
+                    .col(RateLimitBucket::FullAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .if_exists()
+                    .table(RateLimitBucket::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,155 @@
+use sea_orm_migration::prelude::*;
+
+use entity::{Country, CountryCleanup, RefCountEvent};
+
+use crate::sea_orm::EntityName;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum EventFields {
+    Id,
+    CountryId,
+    Previous,
+    Amount,
+    Forced,
+    Action,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum CleanupFields {
+    CountryId,
+    EventId,
+    MarkedAt,
+}
+
+#[derive(DeriveIden)]
+enum CountryFields {
+    Id,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RefCountEvent.table_ref())
+                    .comment("引用计数归零记录")
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(EventFields::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(
+                        ColumnDef::new(EventFields::CountryId)
+                            .big_integer()
+                            .not_null()
+                            .comment("地区ID"),
+                    )
+                    .col(
+                        ColumnDef::new(EventFields::Previous)
+                            .big_integer()
+                            .not_null()
+                            .comment("归零前的引用"),
+                    )
+                    .col(
+                        ColumnDef::new(EventFields::Amount)
+                            .big_integer()
+                            .not_null()
+                            .comment("请求减少的数量"),
+                    )
+                    .col(
+                        ColumnDef::new(EventFields::Forced)
+                            .boolean()
+                            .not_null()
+                            .default(false)
+                            .comment("是否强制归零"),
+                    )
+                    .col(
+                        ColumnDef::new(EventFields::Action)
+                            .string_len(32)
+                            .not_null()
+                            .comment("归零后执行的动作"),
+                    )
+                    .col(
+                        ColumnDef::new(EventFields::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .comment("创建时间"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .table(RefCountEvent.table_ref())
+                    .name("idx-ref_count_event-country_id")
+                    .col(EventFields::CountryId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(CountryCleanup.table_ref())
+                    .comment("待清理的地区")
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(CleanupFields::CountryId)
+                            .big_integer()
+                            .not_null()
+                            .primary_key()
+                            .comment("地区ID"),
+                    )
+                    .col(
+                        ColumnDef::new(CleanupFields::EventId)
+                            .big_integer()
+                            .not_null()
+                            .comment("触发标记的归零记录"),
+                    )
+                    .col(
+                        ColumnDef::new(CleanupFields::MarkedAt)
+                            .timestamp()
+                            .not_null()
+                            .comment("标记时间"),
+                    )
+                    //地区被删除时标记随之删除
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-country_cleanup-country_id")
+                            .from(CountryCleanup.table_ref(), CleanupFields::CountryId)
+                            .to(Country.table_ref(), CountryFields::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .if_exists()
+                    .table(CountryCleanup.table_ref())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(
+                Table::drop()
+                    .if_exists()
+                    .table(RefCountEvent.table_ref())
+                    .to_owned(),
+            )
+            .await
+    }
+}
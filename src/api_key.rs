@@ -0,0 +1,320 @@
+use actix_web::http::header::LOCATION;
+use actix_web::middleware::from_fn;
+use actix_web::web::{Data, Json, Path, ServiceConfig};
+use actix_web::{delete, get, post, HttpResponse};
+use chrono::{NaiveDateTime, Utc};
+use entity::{ApiKey, ApiKeyActiveModel, ApiKeyColumn, ApiKeyModel};
+use migration::sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tracing::{info, Instrument};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::auth::{parse_roles, AuthMethod, Principal, Role};
+use crate::error::AppError;
+use crate::openapi::{date_time, described, integer, nullable, object, path_id, string, Document, Operation, Schema};
+use crate::store::table_span;
+
+/// 生成的 key 以此开头，便于在日志或代码仓库中识别泄露
+pub const KEY_PREFIX: &str = "tl_";
+/// 保存并展示的 key 前缀长度
+const DISPLAY_PREFIX_LEN: usize = 11;
+/// 与表结构 `varchar(100)` 一致
+const NAME_MAX_LEN: usize = 100;
+
+/// 生成新 key：两个 v4 uuid，共 244 位系统随机数
+pub fn generate() -> String {
+    format!("{}{}{}", KEY_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// key 本身是高熵随机数，SHA-256 即可，不需要慢哈希
+pub fn hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// 返回给管理接口的 key 信息，不含哈希
+#[derive(Debug, Serialize)]
+pub struct ApiKeyView {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub roles: Vec<Role>,
+    pub tenant: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl From<ApiKeyModel> for ApiKeyView {
+    fn from(model: ApiKeyModel) -> Self {
+        ApiKeyView {
+            id: model.id,
+            name: model.name,
+            prefix: model.prefix,
+            roles: parse_roles(&model.roles),
+            tenant: model.tenant,
+            created_at: model.created_at,
+            revoked_at: model.revoked_at,
+        }
+    }
+}
+
+/// 创建接口的响应，明文 key 只返回这一次
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKeyView,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateApiKey {
+    pub name: String,
+    pub roles: Vec<Role>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl CreateApiKey {
+    pub fn validate(mut self) -> Result<Self, AppError> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() {
+            return Err(AppError::Validation("name must not be empty".to_string()));
+        }
+        if self.name.chars().count() > NAME_MAX_LEN {
+            return Err(AppError::Validation(format!("name must be at most {} characters", NAME_MAX_LEN)));
+        }
+        if self.roles.is_empty() {
+            return Err(AppError::Validation("roles must not be empty".to_string()));
+        }
+        self.roles.sort();
+        self.roles.dedup();
+        self.tenant = self.tenant.map(|tenant| tenant.trim().to_string()).filter(|tenant| !tenant.is_empty());
+        if self.tenant.as_ref().is_some_and(|tenant| tenant.chars().count() > NAME_MAX_LEN) {
+            return Err(AppError::Validation(format!("tenant must be at most {} characters", NAME_MAX_LEN)));
+        }
+        Ok(self)
+    }
+}
+
+/// 保存 key 的哈希，返回记录与明文 key
+pub async fn create_api_key<C: ConnectionTrait>(db: &C, body: CreateApiKey) -> Result<CreatedApiKey, AppError> {
+    let key = generate();
+    let roles: Vec<String> = body.roles.iter().map(Role::to_string).collect();
+    let model = ApiKeyActiveModel {
+        name: Set(body.name),
+        prefix: Set(key[..DISPLAY_PREFIX_LEN].to_string()),
+        key_hash: Set(hash(&key)),
+        roles: Set(roles.join(",")),
+        tenant: Set(body.tenant),
+        created_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .insert(db)
+    .instrument(table_span("api_key", "insert", "insert api_key"))
+    .await?;
+    info!(api_key.id = model.id, api_key.roles = %model.roles, "api key created");
+    Ok(CreatedApiKey {
+        api_key: model.into(),
+        key,
+    })
+}
+
+/// 按哈希查找未吊销的 key
+pub async fn principal<C: ConnectionTrait>(db: &C, key: &str) -> Result<Option<Principal>, AppError> {
+    if !key.starts_with(KEY_PREFIX) {
+        return Ok(None);
+    }
+    let model = ApiKey::find()
+        .filter(ApiKeyColumn::KeyHash.eq(hash(key)))
+        .filter(ApiKeyColumn::RevokedAt.is_null())
+        .one(db)
+        .instrument(table_span("api_key", "select", "find api_key by key_hash"))
+        .await?;
+    Ok(model.map(|model| Principal {
+        user_id: format!("api_key:{}", model.id),
+        tenant: model.tenant,
+        auth_method: AuthMethod::ApiKey,
+        roles: parse_roles(&model.roles),
+    }))
+}
+
+#[post("/admin/api-keys", wrap = "from_fn(auth::require_admin)")]
+pub async fn create(conn: Data<DatabaseConnection>, body: Json<CreateApiKey>) -> Result<HttpResponse, AppError> {
+    let body = body.into_inner().validate()?;
+    let created = create_api_key(conn.get_ref(), body).await?;
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/admin/api-keys/{}", created.api_key.id)))
+        .json(created))
+}
+
+#[get("/admin/api-keys", wrap = "from_fn(auth::require_admin)")]
+pub async fn list(conn: Data<DatabaseConnection>) -> Result<HttpResponse, AppError> {
+    let keys = ApiKey::find()
+        .order_by_asc(ApiKeyColumn::Id)
+        .all(conn.get_ref())
+        .instrument(table_span("api_key", "select", "list api_key"))
+        .await?;
+    Ok(HttpResponse::Ok().json(keys.into_iter().map(ApiKeyView::from).collect::<Vec<_>>()))
+}
+
+/// 吊销后立即失效，记录保留；重复吊销直接返回 204
+#[delete("/admin/api-keys/{id}", wrap = "from_fn(auth::require_admin)")]
+pub async fn revoke(conn: Data<DatabaseConnection>, id: Path<i64>) -> Result<HttpResponse, AppError> {
+    let id = id.into_inner();
+    let db = conn.get_ref();
+    let model = ApiKey::find_by_id(id)
+        .one(db)
+        .instrument(table_span("api_key", "select", "find api_key by id"))
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("api key {} not found", id)))?;
+    if model.revoked_at.is_none() {
+        let mut model: ApiKeyActiveModel = model.into();
+        model.revoked_at = Set(Some(Utc::now().naive_utc()));
+        model
+            .update(db)
+            .instrument(table_span("api_key", "update", "revoke api_key"))
+            .await?;
+        info!(api_key.id = id, "api key revoked");
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(create).service(list).service(revoke);
+}
+
+fn roles_schema() -> Value {
+    json!({"type": "array", "items": {"type": "string", "enum": ["reader", "writer", "admin"]}})
+}
+
+impl Schema for ApiKeyView {
+    const NAME: &'static str = "ApiKey";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("id", integer()),
+                ("name", string()),
+                ("prefix", described(string(), "key 的前几位，用于辨认")),
+                ("roles", roles_schema()),
+                ("tenant", nullable(string())),
+                ("created_at", date_time()),
+                ("revoked_at", nullable(date_time())),
+            ],
+            &["id", "name", "prefix", "roles", "tenant", "created_at", "revoked_at"],
+        )
+    }
+
+    fn example() -> Value {
+        json!({
+            "id": 1,
+            "name": "ci",
+            "prefix": "tl_2cd1ab48",
+            "roles": ["reader"],
+            "tenant": null,
+            "created_at": "2024-05-01T08:00:00",
+            "revoked_at": null,
+        })
+    }
+}
+
+impl Schema for CreatedApiKey {
+    const NAME: &'static str = "CreatedApiKey";
+
+    fn schema() -> Value {
+        let mut schema = ApiKeyView::schema();
+        schema["properties"]["key"] = described(string(), "明文 key，只返回这一次");
+        schema["required"].as_array_mut().expect("required").push(json!("key"));
+        schema
+    }
+
+    fn example() -> Value {
+        let mut example = ApiKeyView::example();
+        example["key"] = json!(format!("{}2cd1ab48b58c4877958773be9756c05cc0098abe8cc34cd6819471a2d07e43d8", KEY_PREFIX));
+        example
+    }
+}
+
+impl Schema for CreateApiKey {
+    const NAME: &'static str = "CreateApiKey";
+
+    fn schema() -> Value {
+        let mut roles = roles_schema();
+        roles["minItems"] = json!(1);
+        object(
+            &[
+                ("name", json!({"type": "string", "minLength": 1, "maxLength": NAME_MAX_LEN})),
+                ("roles", roles),
+                ("tenant", nullable(json!({"type": "string", "maxLength": NAME_MAX_LEN}))),
+            ],
+            &["name", "roles"],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"name": "ci", "roles": ["reader"], "tenant": "acme"})
+    }
+}
+
+pub fn openapi(doc: &mut Document) {
+    let create_body = doc.schema::<CreateApiKey>();
+    let created = doc.schema::<CreatedApiKey>();
+    doc.route(
+        "post",
+        "/admin/api-keys",
+        Operation::new("createApiKey", "创建 API key", "admin")
+            .role(Role::Admin)
+            .body(create_body)
+            .response("201", "已创建，响应中包含明文 key", Some(created))
+            .response_header("201", "Location", "新资源的地址")
+            .error("400", "请求体不合法"),
+    );
+    let api_key = doc.schema::<ApiKeyView>();
+    doc.route(
+        "get",
+        "/admin/api-keys",
+        Operation::new("listApiKeys", "列出 API key，不含明文与哈希", "admin")
+            .role(Role::Admin)
+            .response("200", "全部 key", Some(json!({"type": "array", "items": api_key}))),
+    );
+    doc.route(
+        "delete",
+        "/admin/api-keys/{id}",
+        Operation::new("revokeApiKey", "吊销 API key", "admin")
+            .role(Role::Admin)
+            .parameter(path_id("API key id"))
+            .response("204", "已吊销", None)
+            .error("404", "不存在"),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use crate::api_key::{generate, hash, CreateApiKey, KEY_PREFIX};
+    use crate::auth::Role;
+
+    #[test]
+    fn test_generate() {
+        let key = generate();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(key.len(), KEY_PREFIX.len() + 64);
+        assert_ne!(key, generate());
+        assert_eq!(hash(&key).len(), 64);
+        assert_eq!(hash(&key), hash(&key));
+    }
+
+    #[test]
+    fn test_create_validation() -> Result<(), anyhow::Error> {
+        let body: CreateApiKey = serde_json::from_str(r#"{"name":" ci ","roles":["writer","reader","writer"]}"#)?;
+        let body = body.validate()?;
+        assert_eq!(body.name, "ci");
+        assert_eq!(body.roles, vec![Role::Reader, Role::Writer]);
+        let empty: CreateApiKey = serde_json::from_str(r#"{"name":"ci","roles":[]}"#)?;
+        assert!(empty.validate().is_err());
+        assert!(serde_json::from_str::<CreateApiKey>(r#"{"name":"ci","roles":["root"]}"#).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+use std::fmt::{Display, Formatter};
+use std::future::{ready, Ready};
+use std::str::FromStr;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, ResponseError};
+use anyhow::anyhow;
+use chrono::Utc;
+use clap::ValueEnum;
+use migration::sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use tracing::Span;
+use tracing_actix_web::RootSpan;
+
+use crate::api_key;
+use crate::error::AppError;
+use crate::jwt::JwtVerifier;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// 由前置网关认证后通过请求头传入
+    Gateway,
+    /// 数据库中的静态 API key
+    ApiKey,
+    /// `Authorization: Bearer` 中的 JWT
+    Jwt,
+}
+
+impl Display for AuthMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthMethod::Gateway => f.write_str("gateway"),
+            AuthMethod::ApiKey => f.write_str("api_key"),
+            AuthMethod::Jwt => f.write_str("jwt"),
+        }
+    }
+}
+
+/// 角色，高级别包含低级别的权限
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// 只读接口
+    Reader,
+    /// 修改业务数据
+    Writer,
+    /// 管理接口，例如日志与 API key
+    Admin,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Reader => f.write_str("reader"),
+            Role::Writer => f.write_str("writer"),
+            Role::Admin => f.write_str("admin"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reader" => Ok(Role::Reader),
+            "writer" => Ok(Role::Writer),
+            "admin" => Ok(Role::Admin),
+            _ => Err(anyhow!("unknown role {}, expected reader, writer or admin", s)),
+        }
+    }
+}
+
+/// 逗号分隔的角色列表，忽略未知角色，外部系统可能带有与本服务无关的角色
+pub fn parse_roles(value: &str) -> Vec<Role> {
+    value.split(',').filter_map(|name| name.trim().parse().ok()).collect()
+}
+
+/// 已认证的调用方
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Principal {
+    pub user_id: String,
+    pub tenant: Option<String>,
+    pub auth_method: AuthMethod,
+    pub roles: Vec<Role>,
+}
+
+impl Principal {
+    /// 写入 `DomainRootSpanBuilder` 预先声明的字段，请求内的所有日志都会带上调用方身份
+    pub fn record(&self, span: &Span) {
+        span.record("user_id", self.user_id.as_str());
+        if let Some(tenant) = &self.tenant {
+            span.record("tenant", tenant.as_str());
+        }
+        span.record("auth_method", tracing::field::display(self.auth_method));
+        let roles: Vec<String> = self.roles.iter().map(Role::to_string).collect();
+        span.record("roles", roles.join(","));
+    }
+
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.iter().any(|granted| *granted >= role)
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let principal = req.extensions().get::<Principal>().cloned();
+        ready(principal.ok_or_else(|| AppError::Unauthorized("authentication required".to_string()).into()))
+    }
+}
+
+/// 认证配置，从 `AUTH_` 前缀的环境变量读取
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// 仅在服务只能经由网关访问时开启，否则调用方可以伪造身份
+    pub trust_gateway_headers: bool,
+    pub user_header: String,
+    pub tenant_header: String,
+    /// 网关传入的逗号分隔角色
+    pub roles_header: String,
+    pub api_key_header: String,
+    /// HS256 共享密钥文件，至少 32 字节
+    pub jwt_secret_file: Option<String>,
+    /// RS256 公钥 PEM 文件
+    pub jwt_public_key_file: Option<String>,
+    /// 设置后要求 `iss` 一致
+    pub jwt_issuer: Option<String>,
+    /// 设置后要求 `aud` 包含该值
+    pub jwt_audience: Option<String>,
+    /// 校验 `exp`、`nbf` 时允许的时钟偏差，秒
+    pub jwt_leeway: u64,
+    pub jwt_roles_claim: String,
+    pub jwt_tenant_claim: String,
+    /// 未认证请求拥有的角色，缺省没有，所有受保护的接口都需要认证
+    pub anonymous_role: Option<Role>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            trust_gateway_headers: false,
+            user_header: "x-user-id".to_string(),
+            tenant_header: "x-tenant-id".to_string(),
+            roles_header: "x-user-roles".to_string(),
+            api_key_header: "x-api-key".to_string(),
+            jwt_secret_file: None,
+            jwt_public_key_file: None,
+            jwt_issuer: None,
+            jwt_audience: None,
+            jwt_leeway: 60,
+            jwt_roles_claim: "roles".to_string(),
+            jwt_tenant_claim: "tenant".to_string(),
+            anonymous_role: None,
+        }
+    }
+}
+
+impl AuthConfig {
+    fn gateway_principal(&self, req: &ServiceRequest) -> Option<Principal> {
+        if !self.trust_gateway_headers {
+            return None;
+        }
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Some(Principal {
+            user_id: header(&self.user_header)?,
+            tenant: header(&self.tenant_header),
+            auth_method: AuthMethod::Gateway,
+            roles: header(&self.roles_header).map(|roles| parse_roles(&roles)).unwrap_or_default(),
+        })
+    }
+}
+
+/// 依次尝试网关请求头、API key 与 JWT
+pub struct Authenticator {
+    config: AuthConfig,
+    jwt: Option<JwtVerifier>,
+}
+
+impl Authenticator {
+    /// 读取 JWT 密钥文件，文件缺失或格式错误时启动失败
+    pub fn new(config: AuthConfig) -> Result<Self, anyhow::Error> {
+        let jwt = JwtVerifier::load(&config)?;
+        Ok(Authenticator { config, jwt })
+    }
+
+    /// 没有凭证时返回 `None`；凭证无效时返回 401，不降级为匿名请求
+    pub async fn authenticate(&self, req: &ServiceRequest) -> Result<Option<Principal>, AppError> {
+        if let Some(principal) = self.config.gateway_principal(req) {
+            return Ok(Some(principal));
+        }
+        if let Some(key) = req.headers().get(self.config.api_key_header.as_str()) {
+            let key = key.to_str().map_err(|_| AppError::Unauthorized("invalid API key".to_string()))?;
+            let conn = req
+                .app_data::<Data<DatabaseConnection>>()
+                .ok_or_else(|| AppError::Unauthorized("API keys are not accepted".to_string()))?;
+            return match api_key::principal(conn.get_ref(), key.trim()).await? {
+                Some(principal) => Ok(Some(principal)),
+                None => Err(AppError::Unauthorized("invalid API key".to_string())),
+            };
+        }
+        let Some(authorization) = req.headers().get(AUTHORIZATION) else {
+            return Ok(None);
+        };
+        let token = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| AppError::Unauthorized("expected a Bearer token".to_string()))?;
+        let jwt = self
+            .jwt
+            .as_ref()
+            .ok_or_else(|| AppError::Unauthorized("bearer tokens are not accepted".to_string()))?;
+        let claims = jwt.verify(token.trim(), Utc::now().timestamp())?;
+        Ok(Some(Principal {
+            roles: claims.roles(&self.config.jwt_roles_claim),
+            tenant: claims.string(&self.config.jwt_tenant_claim),
+            user_id: claims.sub,
+            auth_method: AuthMethod::Jwt,
+        }))
+    }
+}
+
+/// 认证中间件：识别调用方，放入请求扩展并记录到根 span
+///
+/// 需注册在 `TracingLogger` 内层。未携带凭证时不拒绝请求，由 [`require`] 或 [`Principal`] 提取器返回 401。
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let principal = match req.app_data::<Data<Authenticator>>() {
+        Some(authenticator) => authenticator.authenticate(&req).await,
+        None => Ok(None),
+    };
+    match principal {
+        Ok(Some(principal)) => {
+            if let Some(root_span) = req.extensions().get::<RootSpan>() {
+                principal.record(root_span);
+            }
+            req.extensions_mut().insert(principal);
+        }
+        Ok(None) => {}
+        Err(error) => return Ok(req.into_response(error.error_response()).map_into_right_body()),
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// 角色守卫，需在 [`authenticate`] 之后执行；未认证返回 401，角色不足返回 403
+pub async fn require(
+    role: Role,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let allowed = match req.extensions().get::<Principal>() {
+        Some(principal) if principal.has_role(role) => Ok(()),
+        Some(_) => Err(AppError::Forbidden(format!("role {} required", role))),
+        None => {
+            let anonymous = req
+                .app_data::<Data<Authenticator>>()
+                .and_then(|authenticator| authenticator.config.anonymous_role);
+            if anonymous.is_some_and(|anonymous| anonymous >= role) {
+                Ok(())
+            } else {
+                Err(AppError::Unauthorized("authentication required".to_string()))
+            }
+        }
+    };
+    if let Err(error) = allowed {
+        return Ok(req.into_response(error.error_response()).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// 用于路由宏的 `wrap = "from_fn(auth::require_reader)"`
+pub async fn require_reader(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    require(Role::Reader, req, next).await
+}
+
+pub async fn require_writer(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    require(Role::Writer, req, next).await
+}
+
+pub async fn require_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    require(Role::Admin, req, next).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::middleware::from_fn;
+    use actix_web::web::Data;
+    use actix_web::{get, App, HttpResponse};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{info, Subscriber};
+    use tracing_actix_web::TracingLogger;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    use crate::auth;
+    use crate::auth::{authenticate, AuthConfig, Authenticator, Principal, Role};
+    use crate::span::DomainRootSpanBuilder;
+
+    type Fields = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// 收集 span 上记录的字段
+    struct FieldLayer(Fields);
+
+    impl Visit for FieldLayer {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.lock().unwrap().push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for FieldLayer {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut FieldLayer(self.0.clone()));
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            values.record(&mut FieldLayer(self.0.clone()));
+        }
+    }
+
+    #[get("/me")]
+    async fn me(principal: Principal) -> HttpResponse {
+        info!("who am i");
+        HttpResponse::Ok().json(principal)
+    }
+
+    fn config(trust: bool) -> Data<Authenticator> {
+        Data::new(Authenticator::new(AuthConfig {
+            trust_gateway_headers: trust,
+            ..Default::default()
+        }).unwrap())
+    }
+
+    #[get("/write", wrap = "from_fn(auth::require_writer)")]
+    async fn write() -> HttpResponse {
+        HttpResponse::NoContent().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_principal_recorded_on_root_span() {
+        let fields: Fields = Arc::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(FieldLayer(fields.clone())));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(config(true))
+                .wrap(from_fn(authenticate))
+                .wrap(TracingLogger::<DomainRootSpanBuilder>::new())
+                .service(me),
+        ).await;
+        let request = actix_web::test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("x-user-id", "42"))
+            .insert_header(("x-tenant-id", "acme"))
+            .insert_header(("x-user-roles", "writer, auditor"))
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert!(response.status().is_success());
+        let fields = fields.lock().unwrap();
+        assert!(fields.contains(&("user_id".to_string(), "\"42\"".to_string())));
+        assert!(fields.contains(&("tenant".to_string(), "\"acme\"".to_string())));
+        assert!(fields.contains(&("auth_method".to_string(), "gateway".to_string())));
+        assert!(fields.contains(&("roles".to_string(), "\"writer\"".to_string())));
+    }
+
+    #[actix_web::test]
+    async fn test_untrusted_headers_ignored() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(config(false))
+                .wrap(from_fn(authenticate))
+                .wrap(TracingLogger::<DomainRootSpanBuilder>::new())
+                .service(me),
+        ).await;
+        let request = actix_web::test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("x-user-id", "42"))
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_role_guard() {
+        let app = |authenticator: Data<Authenticator>| {
+            actix_web::test::init_service(
+                App::new()
+                    .app_data(authenticator)
+                    .wrap(from_fn(authenticate))
+                    .service(write),
+            )
+        };
+        let request = |roles: Option<&str>| {
+            let mut request = actix_web::test::TestRequest::get().uri("/write");
+            if let Some(roles) = roles {
+                request = request.insert_header(("x-user-id", "42")).insert_header(("x-user-roles", roles));
+            }
+            request.to_request()
+        };
+        let app = app(config(true)).await;
+        assert_eq!(actix_web::test::call_service(&app, request(None)).await.status().as_u16(), 401);
+        assert_eq!(actix_web::test::call_service(&app, request(Some("reader"))).await.status().as_u16(), 403);
+        assert_eq!(actix_web::test::call_service(&app, request(Some("writer"))).await.status().as_u16(), 204);
+        //admin 包含 writer 的权限
+        assert_eq!(actix_web::test::call_service(&app, request(Some("admin"))).await.status().as_u16(), 204);
+
+        let anonymous = Data::new(Authenticator::new(AuthConfig {
+            anonymous_role: Some(Role::Writer),
+            ..Default::default()
+        }).unwrap());
+        let app = actix_web::test::init_service(
+            App::new().app_data(anonymous).wrap(from_fn(authenticate)).service(write),
+        ).await;
+        assert_eq!(actix_web::test::call_service(&app, request(None)).await.status().as_u16(), 204);
+    }
+
+    #[actix_web::test]
+    async fn test_bearer_token() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(config(false))
+                .wrap(from_fn(authenticate))
+                .service(me),
+        ).await;
+        //未配置 JWT 密钥时拒绝而不是当作匿名请求
+        let request = actix_web::test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("authorization", "Bearer a.b.c"))
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.status().as_u16(), 401);
+        let request = actix_web::test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("authorization", "Basic dXNlcjpwYXNz"))
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+}
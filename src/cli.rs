@@ -0,0 +1,215 @@
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use migration::sea_orm::{Database, DatabaseConnection};
+use migration::{Migrator, MigratorTrait};
+
+use crate::api_key;
+use crate::api_key::CreateApiKey;
+use crate::auth::Role;
+use crate::config::{redact_url, Config};
+use crate::log_crypto::{open_log, EncryptionKey};
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// 缺省为 `serve`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 启动 HTTP 服务
+    Serve {
+        /// 启动前执行待应用的迁移，也可通过 `SERVER_AUTO_MIGRATE=true` 开启
+        #[arg(long)]
+        migrate: bool,
+    },
+    /// 数据库迁移
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// 加载并校验配置
+    CheckConfig,
+    /// 检查数据库连接与待应用的迁移
+    CheckDb,
+    /// 输出合并后的配置，密钥已屏蔽
+    PrintConfig,
+    /// 输出 OpenAPI 文档，更新仓库中的 openapi.json
+    Openapi,
+    /// 创建 API key 并输出明文，用于创建第一个管理员 key
+    CreateApiKey {
+        #[arg(long)]
+        name: String,
+        /// 可重复指定
+        #[arg(long = "role", value_enum, required = true)]
+        roles: Vec<Role>,
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+    /// 读取日志文件
+    Logs {
+        #[command(subcommand)]
+        action: LogsAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LogsAction {
+    /// 按行输出日志文件，加密文件使用 `LOG_ENCRYPTION_KEY` 解密，明文文件原样输出
+    Decrypt {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// 只输出包含该文本的行
+        #[arg(long)]
+        grep: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateAction {
+    /// 应用待执行的迁移
+    Up {
+        /// 最多应用的数量，缺省全部
+        #[arg(long)]
+        steps: Option<u32>,
+    },
+    /// 回滚已应用的迁移
+    Down {
+        #[arg(long, default_value_t = 1)]
+        steps: u32,
+    },
+    /// 列出迁移及其状态
+    Status,
+    /// 删除所有表后重新应用全部迁移
+    Fresh {
+        /// 确认清空数据库
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+/// 非 serve 子命令只输出到终端
+fn init_cli_logging() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .init();
+}
+
+async fn connect(config: &Config) -> Result<DatabaseConnection, anyhow::Error> {
+    Database::connect(config.database.connect_options())
+        .await
+        .context("cannot connect to database")
+}
+
+pub async fn migrate(config: &Config, action: MigrateAction) -> Result<(), anyhow::Error> {
+    if let MigrateAction::Fresh { yes: false } = action {
+        bail!("migrate fresh drops every table, pass --yes to confirm")
+    }
+    init_cli_logging();
+    let conn = connect(config).await?;
+    match action {
+        MigrateAction::Up { steps } => Migrator::up(&conn, steps).await?,
+        MigrateAction::Down { steps } => Migrator::down(&conn, Some(steps)).await?,
+        MigrateAction::Fresh { .. } => Migrator::fresh(&conn).await?,
+        MigrateAction::Status => {
+            for migration in Migrator::get_migration_with_status(&conn).await? {
+                println!("{:<8} {}", migration.status().to_string(), migration.name());
+            }
+        }
+    }
+    conn.close().await?;
+    Ok(())
+}
+
+pub fn check_config(config: &Config) -> Result<(), anyhow::Error> {
+    //Config::load 已完成校验
+    println!("config ok: bind {} database {}", config.server.bind, redact_url(&config.database.url));
+    Ok(())
+}
+
+pub async fn check_db(config: &Config) -> Result<(), anyhow::Error> {
+    let start = Instant::now();
+    let conn = connect(config).await?;
+    conn.ping().await.context("database ping failed")?;
+    println!("database reachable in {:?}", start.elapsed());
+    let pending = Migrator::get_pending_migrations(&conn).await?;
+    conn.close().await?;
+    if !pending.is_empty() {
+        let names: Vec<_> = pending.iter().map(|migration| migration.name().to_string()).collect();
+        bail!("{} pending migration(s): {}", names.len(), names.join(", "))
+    }
+    println!("no pending migrations");
+    Ok(())
+}
+
+pub async fn create_api_key(
+    config: &Config,
+    name: String,
+    roles: Vec<Role>,
+    tenant: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let body = CreateApiKey { name, roles, tenant }.validate()?;
+    let conn = connect(config).await?;
+    let created = api_key::create_api_key(&conn, body).await?;
+    conn.close().await?;
+    println!("created api key {} ({})", created.api_key.id, created.api_key.prefix);
+    //明文不会再次显示
+    println!("{}", created.key);
+    Ok(())
+}
+
+pub fn logs(config: &Config, action: LogsAction) -> Result<(), anyhow::Error> {
+    let LogsAction::Decrypt { files, grep } = action;
+    let key = config.log.encryption_key
+        .as_deref()
+        .map(EncryptionKey::from_hex)
+        .transpose()?;
+    let mut stdout = std::io::stdout().lock();
+    for file in files {
+        for line in open_log(&file, key.as_ref())?.lines() {
+            let line = line.with_context(|| format!("cannot read {}", file.display()))?;
+            if grep.as_deref().is_none_or(|grep| line.contains(grep)) {
+                writeln!(stdout, "{}", line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use crate::auth::Role;
+    use crate::cli::{Cli, Command, LogsAction, MigrateAction};
+
+    #[test]
+    fn test_parse_subcommands() -> Result<(), anyhow::Error> {
+        let cli = Cli::try_parse_from(["tokio-learn"])?;
+        assert!(cli.command.is_none());
+        let cli = Cli::try_parse_from(["tokio-learn", "serve", "--migrate"])?;
+        assert!(matches!(cli.command, Some(Command::Serve { migrate: true })));
+        let cli = Cli::try_parse_from(["tokio-learn", "migrate", "down", "--steps", "2"])?;
+        assert!(matches!(cli.command, Some(Command::Migrate { action: MigrateAction::Down { steps: 2 } })));
+        let cli = Cli::try_parse_from(["tokio-learn", "migrate", "fresh"])?;
+        assert!(matches!(cli.command, Some(Command::Migrate { action: MigrateAction::Fresh { yes: false } })));
+        assert!(Cli::try_parse_from(["tokio-learn", "migrate"]).is_err());
+        let cli = Cli::try_parse_from(["tokio-learn", "create-api-key", "--name", "ops", "--role", "admin"])?;
+        assert!(matches!(cli.command, Some(Command::CreateApiKey { roles, .. }) if roles == vec![Role::Admin]));
+        assert!(Cli::try_parse_from(["tokio-learn", "create-api-key", "--name", "ops"]).is_err());
+        let cli = Cli::try_parse_from(["tokio-learn", "logs", "decrypt", "a.log", "b.log", "--grep", "error"])?;
+        assert!(matches!(
+            cli.command,
+            Some(Command::Logs { action: LogsAction::Decrypt { files, grep: Some(_) } }) if files.len() == 2
+        ));
+        assert!(Cli::try_parse_from(["tokio-learn", "logs", "decrypt"]).is_err());
+        Ok(())
+    }
+}
@@ -1,7 +1,10 @@
 use std::fmt::Display;
 use std::future::Future;
+use std::sync::{PoisonError, RwLock};
 use std::time::Duration;
+use serde::{Deserialize, Serialize};
 use tracing::{error, info};
+use crate::metrics::metrics;
 
 pub async fn async_retry<F, Fut, FutT, FutE: Display>(f: F, max_retry: usize, delay: Duration) -> Result<FutT, FutE>
     where F: Fn() -> Fut,
@@ -18,6 +21,7 @@ pub async fn async_retry<F, Fut, FutT, FutE: Display>(f: F, max_retry: usize, de
             }
             Err(e) if retry < max_retry => {
                 error!("错误:{e},重试第{}次",retry+1);
+                metrics().retry_attempts.inc();
                 tokio::time::sleep(delay).await;
                 retry += 1;
                 continue;
@@ -26,3 +30,36 @@ pub async fn async_retry<F, Fut, FutT, FutE: Display>(f: F, max_retry: usize, de
         }
     }
 }
+
+/// 全局重试策略，配置重载时更新
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_retry: usize,
+    /// 毫秒
+    pub delay: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retry: 3,
+            delay: 1000,
+        }
+    }
+}
+
+static RETRY_POLICY: RwLock<RetryPolicy> = RwLock::new(RetryPolicy {
+    max_retry: 3,
+    delay: 1000,
+});
+
+impl RetryPolicy {
+    pub fn current() -> Self {
+        *RETRY_POLICY.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set(self) {
+        *RETRY_POLICY.write().unwrap_or_else(PoisonError::into_inner) = self;
+    }
+}
@@ -0,0 +1,876 @@
+use actix_web::http::header::{EntityTag, ETag, Header, IfMatch, IF_MATCH, LINK, LOCATION};
+use actix_web::middleware::from_fn;
+use actix_web::web::{Data, Json, Path, Query, ServiceConfig};
+use actix_web::{delete, get, patch, post, HttpRequest, HttpResponse};
+use anyhow::anyhow;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use entity::CountryModel;
+use migration::sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::auth;
+use crate::auth::Role;
+use crate::error::AppError;
+use crate::idempotency::Idempotency;
+use crate::store;
+use crate::metrics::metrics;
+use crate::openapi::{date_time, described, header, integer, nullable, object, path_id, string, Document, Operation, Schema};
+use crate::store::{
+    CountryCursor, CountryFilter, CountryPage, CountrySort, ExpectedVersion, RefCountChange, RefCountConfig, RefCountOutcome, ZeroAction,
+};
+
+/// 名称最大长度，与表结构 `varchar(200)` 一致
+pub const NAME_MAX_LEN: usize = 200;
+pub const DEFAULT_LIMIT: u64 = 20;
+pub const MAX_LIMIT: u64 = 100;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateCountry {
+    pub name: String,
+    #[serde(default)]
+    pub ref_count: i64,
+}
+
+/// 只修改传入的字段
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateCountry {
+    pub name: Option<String>,
+    /// 与 increment/decrement 走同一条路径，归零时同样记录事件并执行归零动作
+    pub ref_count: Option<i64>,
+}
+
+/// 分页方式，默认 offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Paging {
+    Offset,
+    /// 基于排序键翻页，翻页期间插入数据不会导致重复或遗漏
+    Keyset,
+}
+
+/// `GET /countries` 的查询参数
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListCountries {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paging: Option<Paging>,
+    /// 上一页返回的 `next_cursor`，隐含 keyset 分页
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// 见 [`CountrySort`]，offset 分页默认 `id`，keyset 分页默认 `created_at`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_from: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ref_count_min: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ref_count_max: Option<i64>,
+}
+
+/// 校验后的列表请求
+#[derive(Debug)]
+pub struct ListRequest {
+    pub filter: CountryFilter,
+    pub sort: CountrySort,
+    pub page: CountryPage,
+    pub limit: u64,
+}
+
+/// 列表响应
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// 符合条件的总数，只在 offset 分页时返回
+    pub total: Option<u64>,
+    /// 下一页的 keyset 游标
+    pub next_cursor: Option<String>,
+    /// 下一页的链接，同时通过 `Link` 响应头返回
+    pub next: Option<String>,
+}
+
+fn validate_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::Validation("name must not be empty".to_string()));
+    }
+    if name.chars().count() > NAME_MAX_LEN {
+        return Err(AppError::Validation(format!("name must be at most {} characters", NAME_MAX_LEN)));
+    }
+    Ok(name.to_string())
+}
+
+fn validate_ref_count(ref_count: i64) -> Result<i64, AppError> {
+    if ref_count < 0 {
+        return Err(AppError::Validation("ref_count must not be negative".to_string()));
+    }
+    Ok(ref_count)
+}
+
+impl CreateCountry {
+    /// 校验并规范化（去除名称首尾空白）
+    pub fn validate(self) -> Result<Self, AppError> {
+        Ok(CreateCountry {
+            name: validate_name(&self.name)?,
+            ref_count: validate_ref_count(self.ref_count)?,
+        })
+    }
+}
+
+impl UpdateCountry {
+    pub fn validate(self) -> Result<Self, AppError> {
+        if self.name.is_none() && self.ref_count.is_none() {
+            return Err(AppError::Validation("at least one of name, ref_count is required".to_string()));
+        }
+        Ok(UpdateCountry {
+            name: self.name.as_deref().map(validate_name).transpose()?,
+            ref_count: self.ref_count.map(validate_ref_count).transpose()?,
+        })
+    }
+}
+
+impl ListCountries {
+    pub fn limit(&self) -> Result<u64, AppError> {
+        match self.limit.unwrap_or(DEFAULT_LIMIT) {
+            limit @ 1..=MAX_LIMIT => Ok(limit),
+            _ => Err(AppError::Validation(format!("limit must be between 1 and {}", MAX_LIMIT))),
+        }
+    }
+
+    pub fn parse(&self) -> Result<ListRequest, AppError> {
+        let paging = match (self.paging, &self.cursor) {
+            (Some(Paging::Offset), Some(_)) => {
+                return Err(AppError::Validation("cursor requires keyset paging".to_string()))
+            }
+            (None, Some(_)) => Paging::Keyset,
+            (paging, _) => paging.unwrap_or(Paging::Offset),
+        };
+        let sort = match (&self.sort, paging) {
+            (Some(sort), _) => sort.parse()?,
+            (None, Paging::Offset) => CountrySort::ID,
+            (None, Paging::Keyset) => CountrySort::CREATED_AT,
+        };
+        let page = match paging {
+            Paging::Offset => CountryPage::Offset(self.offset.unwrap_or(0)),
+            Paging::Keyset if self.offset.is_some() => {
+                return Err(AppError::Validation("offset cannot be combined with keyset paging".to_string()))
+            }
+            Paging::Keyset => CountryPage::Keyset(self.cursor.as_deref().map(decode_cursor).transpose()?),
+        };
+        if let (Some(min), Some(max)) = (self.ref_count_min, self.ref_count_max) {
+            if min > max {
+                return Err(AppError::Validation("ref_count_min must not exceed ref_count_max".to_string()));
+            }
+        }
+        if let (Some(from), Some(before)) = (self.created_from, self.created_before) {
+            if from > before {
+                return Err(AppError::Validation("created_from must not be after created_before".to_string()));
+            }
+        }
+        let filter = CountryFilter {
+            name: self.name.clone(),
+            name_prefix: self.name_prefix.clone(),
+            created_from: self.created_from,
+            created_before: self.created_before,
+            ref_count_min: self.ref_count_min,
+            ref_count_max: self.ref_count_max,
+        };
+        Ok(ListRequest {
+            filter,
+            sort,
+            page,
+            limit: self.limit()?,
+        })
+    }
+
+    fn link(&self) -> Result<String, AppError> {
+        let query = serde_urlencoded::to_string(self).map_err(|e| anyhow!("encode next link: {}", e))?;
+        Ok(format!("/countries?{}", query))
+    }
+}
+
+pub fn encode_cursor(cursor: &CountryCursor) -> Result<String, AppError> {
+    let json = serde_json::to_vec(cursor).map_err(|e| anyhow!("encode cursor: {}", e))?;
+    Ok(URL_SAFE_NO_PAD.encode(json))
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<CountryCursor, AppError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| AppError::Validation("invalid cursor".to_string()))
+}
+
+#[get("/countries", wrap = "from_fn(auth::require_reader)")]
+pub async fn list(conn: Data<DatabaseConnection>, query: Query<ListCountries>) -> Result<HttpResponse, AppError> {
+    let request = query.parse()?;
+    let db = conn.get_ref();
+    //多取一行判断是否还有下一页
+    let mut items = store::list_countries(db, &request.filter, request.sort, &request.page, request.limit + 1).await?;
+    let more = items.len() as u64 > request.limit;
+    items.truncate(request.limit as usize);
+    let (total, next_cursor, next) = match &request.page {
+        CountryPage::Offset(offset) => {
+            //已到最后一页时总数可以直接算出，无需 count
+            let total = if !more && (!items.is_empty() || *offset == 0) {
+                offset + items.len() as u64
+            } else {
+                store::count_countries(db, &request.filter).await?
+            };
+            let next = more.then(|| ListCountries {
+                offset: Some(offset + request.limit),
+                ..query.0.clone()
+            });
+            (Some(total), None, next)
+        }
+        CountryPage::Keyset(_) => match items.last().filter(|_| more) {
+            Some(last) => {
+                let cursor = encode_cursor(&CountryCursor::after(last, request.sort))?;
+                let next = ListCountries {
+                    cursor: Some(cursor.clone()),
+                    ..query.0.clone()
+                };
+                (None, Some(cursor), Some(next))
+            }
+            None => (None, None, None),
+        },
+    };
+    let next = next.as_ref().map(ListCountries::link).transpose()?;
+    let mut response = HttpResponse::Ok();
+    if let Some(next) = &next {
+        response.insert_header((LINK, format!("<{}>; rel=\"next\"", next)));
+    }
+    Ok(response.json(Page {
+        items,
+        total,
+        next_cursor,
+        next,
+    }))
+}
+
+/// 引用计数修改的请求体，`amount` 默认为 1
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChangeRefCount {
+    #[serde(default = "ChangeRefCount::default_amount")]
+    pub amount: i64,
+    /// 只用于减少：不足时减到零，而不是拒绝
+    #[serde(default)]
+    pub force: bool,
+}
+
+impl ChangeRefCount {
+    fn default_amount() -> i64 {
+        1
+    }
+
+    pub fn increment(&self) -> Result<RefCountChange, AppError> {
+        if self.force {
+            return Err(AppError::Validation("force only applies to decrement".to_string()));
+        }
+        Ok(RefCountChange::Increment(self.amount()?))
+    }
+
+    pub fn decrement(&self) -> Result<RefCountChange, AppError> {
+        Ok(RefCountChange::Decrement {
+            amount: self.amount()?,
+            force: self.force,
+        })
+    }
+
+    fn amount(&self) -> Result<i64, AppError> {
+        if self.amount < 1 {
+            return Err(AppError::Validation("amount must be at least 1".to_string()));
+        }
+        Ok(self.amount)
+    }
+}
+
+/// 以版本号作为 ETag
+pub fn etag(country: &CountryModel) -> ETag {
+    ETag(EntityTag::new_strong(country.v.to_string()))
+}
+
+/// 解析 `If-Match`，缺少时返回 428
+///
+/// 按强比较处理：弱 ETag 与非版本号的 ETag 不会匹配任何版本
+pub fn expected_version(req: &HttpRequest) -> Result<ExpectedVersion, AppError> {
+    if !req.headers().contains_key(IF_MATCH) {
+        return Err(AppError::PreconditionRequired("If-Match header is required".to_string()));
+    }
+    match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => Ok(ExpectedVersion::Any),
+        Ok(IfMatch::Items(tags)) => Ok(ExpectedVersion::OneOf(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.tag().parse().ok())
+                .collect(),
+        )),
+        Err(e) => Err(AppError::Validation(format!("invalid If-Match header: {}", e))),
+    }
+}
+
+#[get("/countries/{id}", wrap = "from_fn(auth::require_reader)")]
+pub async fn find(conn: Data<DatabaseConnection>, id: Path<i64>) -> Result<HttpResponse, AppError> {
+    let country = store::find_country(conn.get_ref(), id.into_inner()).await?;
+    Ok(HttpResponse::Ok().insert_header(etag(&country)).json(country))
+}
+
+#[post("/countries", wrap = "from_fn(auth::require_writer)")]
+pub async fn create(
+    conn: Data<DatabaseConnection>,
+    idempotency: Idempotency,
+    body: Json<CreateCountry>,
+) -> Result<HttpResponse, AppError> {
+    let body = body.into_inner().validate()?;
+    idempotency
+        .run(conn.get_ref(), body, |tx, body| {
+            Box::pin(async move {
+                let country = store::create_country(tx, body.name, body.ref_count).await?;
+                Ok(HttpResponse::Created()
+                    .insert_header((LOCATION, format!("/countries/{}", country.id)))
+                    .insert_header(etag(&country))
+                    .json(country))
+            })
+        })
+        .await
+}
+
+/// 需要 `If-Match`，版本不符返回 412
+#[patch("/countries/{id}", wrap = "from_fn(auth::require_writer)")]
+pub async fn update(
+    req: HttpRequest,
+    conn: Data<DatabaseConnection>,
+    config: Option<Data<RefCountConfig>>,
+    idempotency: Idempotency,
+    id: Path<i64>,
+    body: Json<UpdateCountry>,
+) -> Result<HttpResponse, AppError> {
+    let expected = expected_version(&req)?;
+    let body = body.into_inner().validate()?;
+    let id = id.into_inner();
+    let on_zero = config.map(|config| config.on_zero).unwrap_or_default();
+    idempotency
+        .run(conn.get_ref(), body, |tx, body| {
+            Box::pin(async move {
+                let outcome = store::update_country(tx, id, &expected, body.name, body.ref_count, on_zero).await?;
+                let mut response = HttpResponse::Ok();
+                record_zero_reached(&outcome);
+                if outcome.zero_reached != Some(ZeroAction::Delete) {
+                    response.insert_header(etag(&outcome.country));
+                }
+                Ok(response.json(outcome.country))
+            })
+        })
+        .await
+}
+
+/// 需要 `If-Match`，版本不符返回 412
+#[delete("/countries/{id}", wrap = "from_fn(auth::require_writer)")]
+pub async fn remove(req: HttpRequest, conn: Data<DatabaseConnection>, id: Path<i64>) -> Result<HttpResponse, AppError> {
+    let expected = expected_version(&req)?;
+    store::delete_country(conn.get_ref(), id.into_inner(), &expected).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// `If-Match` 可选，不带时仍按读取到的版本做乐观锁校验
+#[post("/countries/{id}/increment", wrap = "from_fn(auth::require_writer)")]
+pub async fn increment(
+    req: HttpRequest,
+    conn: Data<DatabaseConnection>,
+    config: Option<Data<RefCountConfig>>,
+    idempotency: Idempotency,
+    id: Path<i64>,
+    body: Json<ChangeRefCount>,
+) -> Result<HttpResponse, AppError> {
+    change_ref_count(&req, conn.get_ref(), config, idempotency, id.into_inner(), body.increment()?).await
+}
+
+/// 不足时返回 409，`force` 时减到零为止；`If-Match` 可选
+#[post("/countries/{id}/decrement", wrap = "from_fn(auth::require_writer)")]
+pub async fn decrement(
+    req: HttpRequest,
+    conn: Data<DatabaseConnection>,
+    config: Option<Data<RefCountConfig>>,
+    idempotency: Idempotency,
+    id: Path<i64>,
+    body: Json<ChangeRefCount>,
+) -> Result<HttpResponse, AppError> {
+    change_ref_count(&req, conn.get_ref(), config, idempotency, id.into_inner(), body.decrement()?).await
+}
+
+fn record_zero_reached(outcome: &RefCountOutcome) {
+    if let Some(action) = outcome.zero_reached {
+        metrics().ref_count_zero_reached.with_label_values(&[&action.to_string()]).inc();
+    }
+}
+
+async fn change_ref_count(
+    req: &HttpRequest,
+    conn: &DatabaseConnection,
+    config: Option<Data<RefCountConfig>>,
+    idempotency: Idempotency,
+    id: i64,
+    change: RefCountChange,
+) -> Result<HttpResponse, AppError> {
+    let expected = if req.headers().contains_key(IF_MATCH) {
+        expected_version(req)?
+    } else {
+        ExpectedVersion::Any
+    };
+    let on_zero = config.map(|config| config.on_zero).unwrap_or_default();
+    idempotency
+        .run(conn, change, |tx, change| {
+            Box::pin(async move {
+                let outcome = store::change_ref_count(tx, id, &expected, change, on_zero).await?;
+                let mut response = HttpResponse::Ok();
+                record_zero_reached(&outcome);
+                if outcome.zero_reached != Some(ZeroAction::Delete) {
+                    response.insert_header(etag(&outcome.country));
+                }
+                Ok(response.json(outcome))
+            })
+        })
+        .await
+}
+
+impl Schema for CountryModel {
+    const NAME: &'static str = "Country";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("id", integer()),
+                ("name", json!({"type": "string", "maxLength": NAME_MAX_LEN})),
+                ("ref_count", json!({"type": "integer", "format": "int64", "minimum": 0})),
+                ("v", described(integer(), "版本号，同时作为 ETag")),
+                ("created_at", date_time()),
+            ],
+            &["id", "name", "ref_count", "v", "created_at"],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"id": 1, "name": "中国", "ref_count": 0, "v": 0, "created_at": "2024-05-01T08:00:00"})
+    }
+}
+
+impl Schema for CreateCountry {
+    const NAME: &'static str = "CreateCountry";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("name", json!({"type": "string", "minLength": 1, "maxLength": NAME_MAX_LEN})),
+                ("ref_count", json!({"type": "integer", "format": "int64", "minimum": 0, "default": 0})),
+            ],
+            &["name"],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"name": "中国", "ref_count": 0})
+    }
+}
+
+impl Schema for UpdateCountry {
+    const NAME: &'static str = "UpdateCountry";
+
+    fn schema() -> Value {
+        let mut schema = object(
+            &[
+                ("name", json!({"type": "string", "minLength": 1, "maxLength": NAME_MAX_LEN})),
+                ("ref_count", described(json!({"type": "integer", "format": "int64", "minimum": 0}), "改为 0 时与 decrement 一样记录归零事件并执行归零动作")),
+            ],
+            &[],
+        );
+        schema["minProperties"] = json!(1);
+        schema
+    }
+
+    fn example() -> Value {
+        json!({"name": "中华人民共和国", "ref_count": 1})
+    }
+}
+
+impl Schema for ChangeRefCount {
+    const NAME: &'static str = "ChangeRefCount";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("amount", json!({"type": "integer", "format": "int64", "minimum": 1, "default": 1})),
+                ("force", described(json!({"type": "boolean", "default": false}), "只用于减少：不足时减到零")),
+            ],
+            &[],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"amount": 1, "force": false})
+    }
+}
+
+impl Schema for ListCountries {
+    const NAME: &'static str = "ListCountries";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("limit", json!({"type": "integer", "minimum": 1, "maximum": MAX_LIMIT, "default": DEFAULT_LIMIT})),
+                ("offset", json!({"type": "integer", "minimum": 0})),
+                ("paging", json!({"type": "string", "enum": ["offset", "keyset"], "default": "offset"})),
+                ("cursor", described(string(), "上一页的 next_cursor，隐含 keyset 分页")),
+                (
+                    "sort",
+                    described(
+                        json!({"type": "string", "pattern": "^-?(id|name|ref_count|created_at)$"}),
+                        "`-` 前缀为降序，keyset 分页只支持 id 与 created_at",
+                    ),
+                ),
+                ("name", string()),
+                ("name_prefix", string()),
+                ("created_from", described(date_time(), "包含")),
+                ("created_before", described(date_time(), "不包含")),
+                ("ref_count_min", integer()),
+                ("ref_count_max", integer()),
+            ],
+            &[],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"limit": 20, "sort": "-created_at", "name_prefix": "中"})
+    }
+}
+
+impl Schema for Page<CountryModel> {
+    const NAME: &'static str = "CountryPage";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("items", json!({"type": "array", "items": {"$ref": "#/components/schemas/Country"}})),
+                ("total", nullable(described(integer(), "只在 offset 分页时返回"))),
+                ("next_cursor", nullable(string())),
+                ("next", nullable(described(string(), "下一页的链接，同时通过 Link 响应头返回"))),
+            ],
+            &["items", "total", "next_cursor", "next"],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"items": [CountryModel::example()], "total": 1, "next_cursor": null, "next": null})
+    }
+}
+
+impl Schema for RefCountOutcome {
+    const NAME: &'static str = "RefCountOutcome";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("country", json!({"$ref": "#/components/schemas/Country"})),
+                ("previous", integer()),
+                (
+                    "zero_reached",
+                    nullable(described(
+                        json!({"type": "string", "enum": ["none", "mark_for_cleanup", "delete"]}),
+                        "本次修改使引用计数归零时为执行的动作",
+                    )),
+                ),
+            ],
+            &["country", "previous", "zero_reached"],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"country": CountryModel::example(), "previous": 1, "zero_reached": "mark_for_cleanup"})
+    }
+}
+
+/// 注册 `/countries` 下的全部接口
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(list)
+        .service(find)
+        .service(create)
+        .service(update)
+        .service(remove)
+        .service(increment)
+        .service(decrement);
+}
+
+pub fn openapi(doc: &mut Document) {
+    let country = doc.schema::<CountryModel>();
+    let page = doc.schema::<Page<CountryModel>>();
+    let create_body = doc.schema::<CreateCountry>();
+    let update_body = doc.schema::<UpdateCountry>();
+    let etag = "当前版本，修改时放入 If-Match";
+    let if_match = "版本号，`*` 表示任意版本";
+    doc.route(
+        "get",
+        "/countries",
+        Operation::new("listCountries", "分页查询国家", "countries")
+            .role(Role::Reader)
+            .query::<ListCountries>()
+            .response("200", "当前页", Some(page))
+            .response_header("200", "Link", "下一页，rel=\"next\"")
+            .error("400", "查询参数不合法"),
+    );
+    doc.route(
+        "post",
+        "/countries",
+        Operation::new("createCountry", "创建国家", "countries")
+            .role(Role::Writer)
+            .body(create_body)
+            .response("201", "已创建", Some(country.clone()))
+            .response_header("201", "Location", "新资源的地址")
+            .response_header("201", "ETag", etag)
+            .error("400", "请求体不合法")
+            .idempotent(),
+    );
+    doc.route(
+        "get",
+        "/countries/{id}",
+        Operation::new("getCountry", "查询国家", "countries")
+            .role(Role::Reader)
+            .parameter(path_id("国家 id"))
+            .response("200", "国家", Some(country.clone()))
+            .response_header("200", "ETag", etag)
+            .error("404", "不存在"),
+    );
+    doc.route(
+        "patch",
+        "/countries/{id}",
+        Operation::new("updateCountry", "修改国家", "countries")
+            .role(Role::Writer)
+            .parameter(path_id("国家 id"))
+            .parameter(header("If-Match", true, if_match))
+            .body(update_body)
+            .response("200", "修改后的国家", Some(country.clone()))
+            .response_header("200", "ETag", etag)
+            .error("400", "请求体不合法")
+            .error("404", "不存在")
+            .error("409", "并发修改冲突")
+            .error("412", "版本不一致")
+            .error("428", "缺少 If-Match")
+            .idempotent(),
+    );
+    doc.route(
+        "delete",
+        "/countries/{id}",
+        Operation::new("deleteCountry", "删除国家", "countries")
+            .role(Role::Writer)
+            .parameter(path_id("国家 id"))
+            .parameter(header("If-Match", true, if_match))
+            .response("204", "已删除", None)
+            .error("404", "不存在")
+            .error("412", "版本不一致")
+            .error("428", "缺少 If-Match"),
+    );
+    let outcome = doc.schema::<RefCountOutcome>();
+    let body = doc.schema::<ChangeRefCount>();
+    for (id, summary, path) in [
+        ("incrementRefCount", "增加引用计数", "/countries/{id}/increment"),
+        ("decrementRefCount", "减少引用计数，归零时执行配置的动作", "/countries/{id}/decrement"),
+    ] {
+        doc.route(
+            "post",
+            path,
+            Operation::new(id, summary, "countries")
+                .role(Role::Writer)
+                .parameter(path_id("国家 id"))
+                .parameter(header("If-Match", false, if_match))
+                .body(body.clone())
+                .response("200", "修改结果", Some(outcome.clone()))
+                .response_header("200", "ETag", "国家未被删除时返回")
+                .error("400", "请求体不合法")
+                .error("404", "不存在")
+                .error("409", "引用计数不足或并发修改冲突")
+                .error("412", "版本不一致")
+                .idempotent(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::middleware::from_fn;
+    use actix_web::test::TestRequest;
+    use actix_web::web::Data;
+    use actix_web::App;
+    use entity::country_cleanup::Entity as CountryCleanup;
+    use entity::ref_count_event::{Column as RefCountEventColumn, Entity as RefCountEvent};
+    use migration::sea_orm::{ColumnTrait, Database, EntityTrait, QueryFilter, TransactionTrait};
+
+    use crate::auth::{authenticate, AuthConfig, Authenticator, Role};
+    use crate::country::{encode_cursor, expected_version, update, ChangeRefCount, CreateCountry, ListCountries, UpdateCountry};
+    use crate::error::AppError;
+    use crate::store::{create_country, delete_country, CountryCursor, CountryPage, CountrySort, ExpectedVersion, RefCountChange};
+
+    #[test]
+    fn test_create_validation() -> Result<(), anyhow::Error> {
+        let body: CreateCountry = serde_json::from_str(r#"{"name":"  中国  "}"#)?;
+        let body = body.validate()?;
+        assert_eq!(body.name, "中国");
+        assert_eq!(body.ref_count, 0);
+        let body: CreateCountry = serde_json::from_str(r#"{"name":"   "}"#)?;
+        assert_eq!(body.validate().unwrap_err().to_string(), "name must not be empty");
+        let body = CreateCountry {
+            name: "国".repeat(201),
+            ref_count: 0,
+        };
+        assert!(body.validate().is_err());
+        let body: CreateCountry = serde_json::from_str(r#"{"name":"a","ref_count":-1}"#)?;
+        assert!(body.validate().is_err());
+        //未知字段直接拒绝，避免客户端以为 v、id 等字段可以写入
+        assert!(serde_json::from_str::<CreateCountry>(r#"{"name":"a","v":3}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_validation() -> Result<(), anyhow::Error> {
+        let body: UpdateCountry = serde_json::from_str("{}")?;
+        assert!(body.validate().is_err());
+        let body: UpdateCountry = serde_json::from_str(r#"{"ref_count":3}"#)?;
+        let body = body.validate()?;
+        assert_eq!(body.ref_count, Some(3));
+        assert!(body.name.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_limit() {
+        assert_eq!(ListCountries::default().limit().unwrap(), 20);
+        assert!(ListCountries { limit: Some(0), ..Default::default() }.limit().is_err());
+        assert!(ListCountries { limit: Some(101), ..Default::default() }.limit().is_err());
+    }
+
+    fn query(query: &str) -> Result<ListCountries, anyhow::Error> {
+        Ok(serde_urlencoded::from_str(query)?)
+    }
+
+    #[test]
+    fn test_list_parse() -> Result<(), anyhow::Error> {
+        let request = query("name_prefix=中&ref_count_min=1&created_before=2024-05-01T00:00:00")?.parse()?;
+        assert!(matches!(request.page, CountryPage::Offset(0)));
+        assert_eq!(request.sort, CountrySort::ID);
+        assert_eq!(request.filter.name_prefix.as_deref(), Some("中"));
+        //keyset 分页默认按创建时间排序以使用 idx-country-created_at
+        let request = query("paging=keyset")?.parse()?;
+        assert!(matches!(request.page, CountryPage::Keyset(None)));
+        assert_eq!(request.sort, CountrySort::CREATED_AT);
+        assert!(query("sort=v")?.parse().is_err());
+        assert!(query("paging=keyset&offset=20")?.parse().is_err());
+        assert!(query("ref_count_min=5&ref_count_max=1")?.parse().is_err());
+        assert!(query("cursor=not-a-cursor")?.parse().is_err());
+        assert!(query("unknown=1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cursor_round_trip() -> Result<(), anyhow::Error> {
+        let cursor = CountryCursor {
+            sort: "-created_at".to_string(),
+            created_at: Some("2024-05-01T08:00:00.123456".parse()?),
+            id: 7,
+        };
+        let encoded = encode_cursor(&cursor)?;
+        let request = query(&format!("cursor={}&sort=-created_at", encoded))?.parse()?;
+        assert!(matches!(request.page, CountryPage::Keyset(Some(decoded)) if decoded == cursor));
+        //下一页链接保留过滤条件
+        let next = ListCountries {
+            cursor: Some(encoded.clone()),
+            ..query("sort=-created_at&name_prefix=a b&limit=5")?
+        };
+        assert_eq!(next.link()?, format!("/countries?limit=5&cursor={}&sort=-created_at&name_prefix=a+b", encoded));
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_match() -> Result<(), anyhow::Error> {
+        let expected = |value: Option<&str>| {
+            let mut request = TestRequest::patch();
+            if let Some(value) = value {
+                request = request.insert_header(("if-match", value));
+            }
+            expected_version(&request.to_http_request())
+        };
+        assert!(matches!(expected(None), Err(AppError::PreconditionRequired(_))));
+        assert_eq!(expected(Some("*"))?, ExpectedVersion::Any);
+        assert_eq!(expected(Some(r#""3", "5""#))?, ExpectedVersion::OneOf(vec![3, 5]));
+        //弱 ETag、非版本号与缺少引号的值不匹配任何版本
+        assert_eq!(expected(Some(r#"W/"3", "abc", 3"#))?, ExpectedVersion::OneOf(vec![]));
+        assert!(!ExpectedVersion::OneOf(vec![]).matches(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_ref_count_body() -> Result<(), anyhow::Error> {
+        let body: ChangeRefCount = serde_json::from_str("{}")?;
+        assert_eq!(body.increment()?, RefCountChange::Increment(1));
+        let body: ChangeRefCount = serde_json::from_str(r#"{"amount":3,"force":true}"#)?;
+        assert_eq!(body.decrement()?, RefCountChange::Decrement { amount: 3, force: true });
+        assert!(body.increment().is_err());
+        let body: ChangeRefCount = serde_json::from_str(r#"{"amount":0}"#)?;
+        assert!(body.decrement().is_err());
+        Ok(())
+    }
+
+    /// 需要数据库，未设置 `DATABASE_URL` 时跳过
+    #[actix_web::test]
+    async fn test_patch_ref_count_to_zero() -> Result<(), anyhow::Error> {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            return Ok(());
+        };
+        let conn = Database::connect(url).await?;
+        let country = create_country(&conn, "test_patch_ref_count_to_zero".to_string(), 2).await?;
+        let authenticator = Authenticator::new(AuthConfig {
+            anonymous_role: Some(Role::Writer),
+            ..Default::default()
+        })?;
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(conn.clone()))
+                .app_data(Data::new(authenticator))
+                .wrap(from_fn(authenticate))
+                .service(update),
+        )
+        .await;
+        let request = TestRequest::patch()
+            .uri(&format!("/countries/{}", country.id))
+            .insert_header(("if-match", format!("\"{}\"", country.v)))
+            .set_json(serde_json::json!({"ref_count": 0}))
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        let events = RefCountEvent::find().filter(RefCountEventColumn::CountryId.eq(country.id)).all(&conn).await?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].previous, 2);
+        assert_eq!(events[0].action, "mark_for_cleanup");
+        assert!(CountryCleanup::find_by_id(country.id).one(&conn).await?.is_some());
+
+        let tx = conn.begin().await?;
+        delete_country(&tx, country.id, &ExpectedVersion::Any).await?;
+        RefCountEvent::delete_many().filter(RefCountEventColumn::CountryId.eq(country.id)).exec(&tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter};
+
+use actix_web::body::BoxBody;
+use actix_web::error::{JsonPayloadError, QueryPayloadError};
+use actix_web::http::StatusCode;
+use actix_web::web::{JsonConfig, QueryConfig};
+use actix_web::{HttpResponse, ResponseError};
+use migration::sea_orm::{DbErr, SqlErr};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::openapi::{described, nullable, object, string, Schema};
+use crate::trace_context::TraceContext;
+
+/// 接口统一的错误类型，响应体为 [`ErrorBody`]
+#[derive(Debug)]
+pub enum AppError {
+    /// 请求参数不合法
+    Validation(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    /// 乐观锁版本校验失败，数据已被其他请求修改
+    VersionConflict(String),
+    /// 唯一约束等数据冲突
+    Conflict(String),
+    /// `If-Match` 与当前版本不一致
+    PreconditionFailed(String),
+    /// 修改请求缺少 `If-Match`
+    PreconditionRequired(String),
+    /// 超过限流配额
+    TooManyRequests(String),
+    /// 相同 `Idempotency-Key` 的请求仍在处理
+    IdempotencyInProgress(String),
+    /// `Idempotency-Key` 已用于不同的请求
+    IdempotencyKeyReused(String),
+    Database(DbErr),
+    Internal(anyhow::Error),
+}
+
+/// 错误响应体
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// 稳定的错误码，客户端据此判断错误类型
+    pub code: &'static str,
+    pub message: String,
+    /// 与响应头 `X-Request-Id` 及日志中的 `x_request_id` 相同
+    pub request_id: Option<String>,
+}
+
+/// 全部错误码，用于接口文档
+pub const ERROR_CODES: [&str; 14] = [
+    "validation_failed",
+    "unauthorized",
+    "forbidden",
+    "not_found",
+    "version_conflict",
+    "conflict",
+    "precondition_failed",
+    "precondition_required",
+    "rate_limited",
+    "idempotency_in_progress",
+    "idempotency_key_reused",
+    "database_unavailable",
+    "database_error",
+    "internal_error",
+];
+
+impl Schema for ErrorBody {
+    const NAME: &'static str = "Error";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("code", json!({"type": "string", "enum": ERROR_CODES})),
+                ("message", string()),
+                ("request_id", described(nullable(string()), "与响应头 X-Request-Id 及日志中的 x_request_id 相同")),
+            ],
+            &["code", "message", "request_id"],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"code": "not_found", "message": "country 7 not found", "request_id": "2f1c6b0e-8a51-4d0c-9f7e-1d2b3c4d5e6f"})
+    }
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_failed",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::VersionConflict(_) => "version_conflict",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed(_) => "precondition_failed",
+            AppError::PreconditionRequired(_) => "precondition_required",
+            AppError::TooManyRequests(_) => "rate_limited",
+            AppError::IdempotencyInProgress(_) => "idempotency_in_progress",
+            AppError::IdempotencyKeyReused(_) => "idempotency_key_reused",
+            AppError::Database(DbErr::ConnectionAcquire(_)) => "database_unavailable",
+            AppError::Database(_) => "database_error",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// 从外到内的错误链，用于日志与 span，不返回给客户端
+    pub fn chain(&self) -> Vec<String> {
+        let mut chain = vec![];
+        match self {
+            AppError::Internal(error) => chain.extend(error.chain().map(|cause| cause.to_string())),
+            AppError::Database(error) => {
+                let mut cause: Option<&dyn StdError> = Some(error);
+                while let Some(error) = cause {
+                    chain.push(error.to_string());
+                    cause = error.source();
+                }
+            }
+            _ => chain.push(self.to_string()),
+        }
+        chain
+    }
+
+    /// 错误链中的数据库错误
+    pub fn db_error(&self) -> Option<&DbErr> {
+        match self {
+            AppError::Database(error) => Some(error),
+            AppError::Internal(error) => error.chain().find_map(|cause| cause.downcast_ref::<DbErr>()),
+            _ => None,
+        }
+    }
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Validation(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::VersionConflict(message)
+            | AppError::Conflict(message)
+            | AppError::PreconditionFailed(message)
+            | AppError::PreconditionRequired(message)
+            | AppError::TooManyRequests(message)
+            | AppError::IdempotencyInProgress(message)
+            | AppError::IdempotencyKeyReused(message) => f.write_str(message),
+            AppError::Database(error) => write!(f, "{}", error),
+            AppError::Internal(error) => write!(f, "{:#}", error),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::VersionConflict(_) | AppError::Conflict(_) | AppError::IdempotencyInProgress(_) => {
+                StatusCode::CONFLICT
+            }
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::IdempotencyKeyReused(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Database(DbErr::ConnectionAcquire(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// 在 `trace_context::propagate` 内生成响应时带上请求 id；5xx 不返回内部细节
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        let status = self.status_code();
+        let message = if status.is_server_error() {
+            status.canonical_reason().unwrap_or("internal server error").to_lowercase()
+        } else {
+            self.to_string()
+        };
+        HttpResponse::build(status).json(ErrorBody {
+            code: self.code(),
+            message,
+            request_id: TraceContext::current().map(|context| context.request_id),
+        })
+    }
+}
+
+impl From<DbErr> for AppError {
+    fn from(error: DbErr) -> Self {
+        match error {
+            DbErr::RecordNotFound(message) => AppError::NotFound(message),
+            error => match error.sql_err() {
+                Some(SqlErr::UniqueConstraintViolation(message)) => AppError::Conflict(message),
+                _ => AppError::Database(error),
+            },
+        }
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<AppError>() {
+            Ok(error) => error,
+            Err(error) => AppError::Internal(error),
+        }
+    }
+}
+
+impl StdError for AppError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            AppError::Database(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// JSON 请求体解析失败时返回统一的错误格式
+pub fn json_config() -> JsonConfig {
+    JsonConfig::default().error_handler(|error: JsonPayloadError, _| AppError::Validation(error.to_string()).into())
+}
+
+/// 查询参数解析失败时返回统一的错误格式
+pub fn query_config() -> QueryConfig {
+    QueryConfig::default().error_handler(|error: QueryPayloadError, _| AppError::Validation(error.to_string()).into())
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::body::to_bytes;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use anyhow::anyhow;
+    use migration::sea_orm::{ConnAcquireErr, DbErr};
+    use serde_json::Value;
+
+    use crate::error::{AppError, ERROR_CODES};
+
+    async fn response_json(error: &AppError) -> Result<Value, anyhow::Error> {
+        let bytes = to_bytes(error.error_response().into_body()).await.map_err(|e| anyhow!("{}", e))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    #[test]
+    fn test_status_mapping() {
+        assert_eq!(AppError::Validation("bad".into()).status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(AppError::from(DbErr::RecordNotFound("country 1".into())).status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(AppError::VersionConflict("v".into()).status_code(), StatusCode::CONFLICT);
+        assert_eq!(AppError::PreconditionFailed("v".into()).status_code(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(AppError::PreconditionRequired("v".into()).status_code(), StatusCode::PRECONDITION_REQUIRED);
+        let unavailable = AppError::from(DbErr::ConnectionAcquire(ConnAcquireErr::Timeout));
+        assert_eq!(unavailable.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(unavailable.code(), "database_unavailable");
+        //anyhow 中包装的 AppError 保持原有类型
+        let wrapped = AppError::from(anyhow::Error::new(AppError::NotFound("country 1".into())));
+        assert_eq!(wrapped.code(), "not_found");
+        //文档中的错误码与实际一致
+        let errors = [
+            AppError::Validation("v".into()),
+            AppError::Unauthorized("v".into()),
+            AppError::Forbidden("v".into()),
+            AppError::NotFound("v".into()),
+            AppError::VersionConflict("v".into()),
+            AppError::Conflict("v".into()),
+            AppError::PreconditionFailed("v".into()),
+            AppError::PreconditionRequired("v".into()),
+            AppError::TooManyRequests("v".into()),
+            AppError::IdempotencyInProgress("v".into()),
+            AppError::IdempotencyKeyReused("v".into()),
+            unavailable,
+            AppError::Database(DbErr::Custom("v".into())),
+            AppError::Internal(anyhow!("v")),
+        ];
+        let codes: Vec<_> = errors.iter().map(AppError::code).collect();
+        assert_eq!(codes, ERROR_CODES);
+    }
+
+    #[actix_web::test]
+    async fn test_json_body() -> Result<(), anyhow::Error> {
+        let body = response_json(&AppError::NotFound("country 7 not found".into())).await?;
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["message"], "country 7 not found");
+        assert!(body["request_id"].is_null());
+        //5xx 不返回内部细节
+        let error = AppError::from(anyhow!("password=secret").context("load country"));
+        let body = response_json(&error).await?;
+        assert_eq!(body["code"], "internal_error");
+        assert_eq!(body["message"], "internal server error");
+        assert_eq!(error.chain(), vec!["load country".to_string(), "password=secret".to_string()]);
+        Ok(())
+    }
+}
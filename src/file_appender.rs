@@ -2,14 +2,18 @@ use std::fs;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::ops::Add;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{PoisonError, RwLock};
-use chrono::{Datelike, DateTime, Local, TimeDelta, Timelike};
+use std::sync::{Arc, PoisonError, RwLock};
+use chrono::{DateTime, Local, TimeDelta, Timelike};
 use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+use crate::metrics::metrics;
+use crate::log_crypto::{is_encrypted, repair_tail, EncryptedWriter, EncryptionKey};
 
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Rotation {
     #[default]
     Daily,
@@ -67,7 +71,7 @@ impl Rotation {
 
 pub struct TracingFileAppender<'a, 'c> {
     state: State<'a, 'c>,
-    writer: RwLock<File>,
+    writer: RwLock<LogFile>,
 }
 
 #[derive(Default, Builder, Debug)]
@@ -76,6 +80,31 @@ pub struct Appender<'c> {
     rotation: Rotation,
     prefix: Option<&'c str>,
     suffix: Option<&'c str>,
+    /// 设置后日志文件以流式 AEAD 加密写入
+    #[builder(default)]
+    encryption: Option<EncryptionKey>,
+    /// 保留的日志文件数，可在运行时调整
+    #[builder(default)]
+    max_files: MaxFiles,
+}
+
+/// 轮转时保留的最多文件数（含当前文件），0 表示不清理
+#[derive(Debug, Default, Clone)]
+pub struct MaxFiles(Arc<AtomicUsize>);
+
+impl MaxFiles {
+    pub fn new(max_files: usize) -> Self {
+        MaxFiles(Arc::new(AtomicUsize::new(max_files)))
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// 新值在下一次轮转时生效
+    pub fn set(&self, max_files: usize) {
+        self.0.store(max_files, Ordering::Relaxed)
+    }
 }
 
 struct State<'a, 'c> {
@@ -84,6 +113,53 @@ struct State<'a, 'c> {
     directory: &'a Path,
     prefix: Option<&'c str>,
     suffix: Option<&'c str>,
+    encryption: Option<EncryptionKey>,
+    max_files: MaxFiles,
+}
+
+enum LogFile {
+    Plain(File),
+    Encrypted(EncryptedWriter<File>),
+}
+
+impl LogFile {
+    fn open(mut file: File, encryption: Option<&EncryptionKey>) -> Result<Self, anyhow::Error> {
+        match encryption {
+            None => Ok(LogFile::Plain(file)),
+            Some(key) => {
+                //追加到已有文件时先截掉崩溃留下的半条记录
+                repair_tail(&mut file)?;
+                Ok(LogFile::Encrypted(EncryptedWriter::new(file, key)?))
+            }
+        }
+    }
+}
+
+impl LogFile {
+    /// 刷新缓冲并落盘
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.flush()?;
+        match self {
+            LogFile::Plain(file) => file.sync_all(),
+            LogFile::Encrypted(writer) => writer.get_ref().sync_all(),
+        }
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            LogFile::Plain(file) => file.write(buf),
+            LogFile::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            LogFile::Plain(file) => file.flush(),
+            LogFile::Encrypted(writer) => writer.flush(),
+        }
+    }
 }
 
 impl<'a, 'c> State<'a, 'c> {
@@ -101,6 +177,8 @@ impl<'a, 'c> State<'a, 'c> {
             directory: directory.as_ref(),
             prefix,
             suffix,
+            encryption: None,
+            max_files: MaxFiles::default(),
         };
         let filename = state.join_date(now);
         let writer_file = Self::create_writer(directory.as_ref(), &filename)?;
@@ -125,10 +203,9 @@ impl<'a, 'c> State<'a, 'c> {
             .next_time(now)
             .map(|date| date.timestamp() as usize)
             .unwrap_or(0);
-        let result = self.next_time
+        self.next_time
             .compare_exchange(current_timestamp, next_time, Ordering::AcqRel, Ordering::Acquire)
-            .is_ok();
-        result
+            .is_ok()
     }
 
     fn join_date(&self, time: Time) -> String {
@@ -149,20 +226,88 @@ impl<'a, 'c> State<'a, 'c> {
         }
     }
 
-    fn refresh_writer(&self, now: Time, file: &mut File) {
+    fn refresh_writer(&self, now: Time, file: &mut LogFile) {
         let filename = self.join_date(now);
-        match Self::create_writer(&self.directory, &filename) {
+        match self.open_log_file(&filename) {
             Ok(new_file) => {
                 if let Err(err) = file.flush() {
+                    metrics().appender_errors.inc();
                     eprintln!("Couldn't flush previous writer: {}", err);
                 }
                 *file = new_file;
+                metrics().appender_rollovers.inc();
+                if let Err(err) = self.prune(&filename) {
+                    metrics().appender_errors.inc();
+                    eprintln!("Couldn't remove old log files: {}", err);
+                }
+            }
+            Err(err) => {
+                metrics().appender_errors.inc();
+                eprintln!("Couldn't create writer for logs: {}", err)
+            }
+        }
+    }
+
+    /// 打开当前周期的日志文件
+    ///
+    /// 运行中开启或关闭加密时，已有文件的格式与配置不一致，不能继续追加，
+    /// 依次改用 `<文件名>.1`、`<文件名>.2` 等文件，直到找到空文件或格式一致的文件
+    fn open_log_file(&self, filename: &str) -> Result<LogFile, anyhow::Error> {
+        let mut name = filename.to_string();
+        let mut index = 0;
+        loop {
+            let file = Self::create_writer(self.directory, &name)?;
+            if file.metadata()?.len() == 0 || is_encrypted(&self.directory.join(&name))? == self.encryption.is_some() {
+                return LogFile::open(file, self.encryption.as_ref());
+            }
+            index += 1;
+            name = format!("{}.{}", filename, index);
+        }
+    }
+
+    /// 删除超出 `max_files` 的旧文件，按文件名中的日期从旧到新，同一周期的 `.1`、`.2` 等文件排在其后
+    fn prune(&self, current: &str) -> Result<(), anyhow::Error> {
+        let max_files = self.max_files.get();
+        if max_files == 0 {
+            return Ok(());
+        }
+        let date_len = Self::now().format(self.rotation.date_format()).to_string().len();
+        let prefix = self.prefix.map(|prefix| format!("{}.", prefix)).unwrap_or_default();
+        let suffix = self.suffix.map(|suffix| format!(".{}", suffix)).unwrap_or_default();
+        let mut files = vec![];
+        for entry in fs::read_dir(self.directory)? {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let (base, index) = match name.rsplit_once('.') {
+                Some((base, index)) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => {
+                    (base, index.parse::<usize>().unwrap_or(usize::MAX))
+                }
+                _ => (name.as_str(), 0),
+            };
+            let date = base
+                .strip_prefix(prefix.as_str())
+                .and_then(|name| name.strip_suffix(suffix.as_str()));
+            let is_log = date.is_some_and(|date| {
+                date.len() == date_len && date.bytes().all(|b| b.is_ascii_digit() || b == b'-')
+            });
+            if is_log && base != current && entry.file_type()?.is_file() {
+                files.push((base.to_string(), index, name.clone()));
             }
-            Err(err) => eprintln!("Couldn't create writer for logs: {}", err),
         }
+        files.sort();
+        //当前文件占一个名额
+        let remove = (files.len() + 1).saturating_sub(max_files);
+        for (_, _, name) in files.into_iter().take(remove) {
+            fs::remove_file(self.directory.join(name))?;
+        }
+        Ok(())
     }
+
     pub(crate) fn create_writer(directory: &Path, filename: &str) -> Result<File, anyhow::Error> {
         let mut open_options = OpenOptions::new();
+        open_options.read(true);
         open_options.append(true);
         open_options.create(true);
         let path = directory.join(filename);
@@ -184,10 +329,11 @@ impl<'a, 'c> State<'a, 'c> {
 
 #[cfg(test)]
 mod test {
+    use std::io::{BufRead, Write};
     use std::ops::Add;
-    use std::path::Path;
-    use chrono::{Datelike, DateTime, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone};
-    use crate::file_appender::{Rotation, State};
+    use chrono::{Local, TimeDelta, TimeZone};
+    use crate::file_appender::{AppenderBuilder, MaxFiles, Rotation, State, TracingFileAppender};
+    use crate::log_crypto::{open_log, EncryptionKey};
 
     #[test]
     fn test_state_add_date_fail() -> Result<(), anyhow::Error> {
@@ -286,6 +432,120 @@ mod test {
         )?.0.join_date(Local.with_ymd_and_hms(2024, 12, 12, 0, 0, 0).unwrap()), "2024-12-12");
         Ok(())
     }
+
+    #[test]
+    fn test_encrypted_appender() -> Result<(), anyhow::Error> {
+        let directory = std::env::temp_dir().join(format!("appender-encrypted-{}", std::process::id()));
+        let key = EncryptionKey::from_hex(&"ab".repeat(32))?;
+        let builder = AppenderBuilder::default()
+            .rotation(Rotation::Never)
+            .prefix(Some("app"))
+            .suffix(Some("log"))
+            .encryption(Some(key.clone()))
+            .clone();
+        {
+            let mut appender = TracingFileAppender::from_builder(builder, &directory)?;
+            appender.write_all(b"customer=42\n")?;
+            appender.flush()?;
+        }
+        let path = directory.join("app.log");
+        assert!(!std::fs::read(&path)?.windows(8).any(|w| w == b"customer"));
+        let lines: Vec<String> = open_log(&path, Some(&key))?.lines().collect::<Result<_, _>>()?;
+        assert_eq!(lines, vec!["customer=42"]);
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    fn write_log(directory: &std::path::Path, key: Option<&EncryptionKey>, line: &[u8]) -> Result<(), anyhow::Error> {
+        let builder = AppenderBuilder::default()
+            .rotation(Rotation::Never)
+            .prefix(Some("app"))
+            .suffix(Some("log"))
+            .encryption(key.cloned())
+            .clone();
+        let mut appender = TracingFileAppender::from_builder(builder, directory)?;
+        appender.write_all(line)?;
+        appender.flush()?;
+        Ok(())
+    }
+
+    fn read_log(path: &std::path::Path, key: &EncryptionKey) -> Result<Vec<String>, anyhow::Error> {
+        Ok(open_log(path, Some(key))?.lines().collect::<Result<_, _>>()?)
+    }
+
+    #[test]
+    fn test_enable_encryption_mid_period() -> Result<(), anyhow::Error> {
+        let directory = std::env::temp_dir().join(format!("appender-enable-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let key = EncryptionKey::from_hex(&"ab".repeat(32))?;
+        write_log(&directory, None, b"plain\n")?;
+        write_log(&directory, Some(&key), b"secret\n")?;
+        write_log(&directory, Some(&key), b"secret again\n")?;
+        assert_eq!(std::fs::read(directory.join("app.log"))?, b"plain\n");
+        assert_eq!(read_log(&directory.join("app.log.1"), &key)?, vec!["secret", "secret again"]);
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disable_encryption_mid_period() -> Result<(), anyhow::Error> {
+        let directory = std::env::temp_dir().join(format!("appender-disable-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let key = EncryptionKey::from_hex(&"ab".repeat(32))?;
+        write_log(&directory, Some(&key), b"secret\n")?;
+        write_log(&directory, None, b"plain\n")?;
+        assert_eq!(read_log(&directory.join("app.log"), &key)?, vec!["secret"]);
+        assert_eq!(std::fs::read(directory.join("app.log.1"))?, b"plain\n");
+        //再次开启加密时回到格式一致的原文件
+        write_log(&directory, Some(&key), b"secret again\n")?;
+        assert_eq!(read_log(&directory.join("app.log"), &key)?, vec!["secret", "secret again"]);
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_keeps_newest() -> Result<(), anyhow::Error> {
+        let directory = "logs/prune-test";
+        let _ = std::fs::remove_dir_all(directory);
+        let (mut state, _) = State::new(State::now(), Rotation::Daily, directory, Some("app"), Some("log"))?;
+        state.max_files = MaxFiles::new(2);
+        let current = state.join_date(State::now());
+        for name in ["app.2024-12-11.log", "app.2024-12-12.log", "app.2024-12-13.log", "other.log"] {
+            std::fs::write(format!("{}/{}", directory, name), "")?;
+        }
+        state.prune(&current)?;
+        let mut names: Vec<_> = std::fs::read_dir(directory)?
+            .map(|entry| entry.map(|entry| entry.file_name().into_string().unwrap()))
+            .collect::<Result<_, _>>()?;
+        names.sort();
+        let mut expected = vec!["app.2024-12-13.log".to_string(), current, "other.log".to_string()];
+        expected.sort();
+        assert_eq!(names, expected);
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_segments() -> Result<(), anyhow::Error> {
+        let directory = "logs/prune-segments-test";
+        let _ = std::fs::remove_dir_all(directory);
+        let (mut state, _) = State::new(State::now(), Rotation::Daily, directory, Some("app"), Some("log"))?;
+        state.max_files = MaxFiles::new(2);
+        let current = state.join_date(State::now());
+        for name in ["app.2024-12-11.log", "app.2024-12-12.log", "app.2024-12-12.log.1", format!("{}.1", current).as_str()] {
+            std::fs::write(format!("{}/{}", directory, name), "")?;
+        }
+        state.prune(&current)?;
+        let mut names: Vec<_> = std::fs::read_dir(directory)?
+            .map(|entry| entry.map(|entry| entry.file_name().into_string().unwrap()))
+            .collect::<Result<_, _>>()?;
+        names.sort();
+        let mut expected = vec!["app.2024-12-12.log.1".to_string(), format!("{}.1", current), current];
+        expected.sort();
+        assert_eq!(names, expected);
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
 }
 
 type Time = DateTime<Local>;
@@ -296,15 +556,21 @@ impl<'a, 'c> TracingFileAppender<'a, 'c> {
             rotation,
             prefix,
             suffix,
+            encryption,
+            max_files,
         } = builder.build()?;
         let now = State::now();
-        let (state, writer) = State::new(
+        let (mut state, writer) = State::new(
             now,
             rotation,
             directory,
             prefix,
             suffix,
         )?;
+        drop(writer);
+        state.encryption = encryption;
+        state.max_files = max_files;
+        let writer = RwLock::new(state.open_log_file(&state.join_date(now))?);
         Ok(TracingFileAppender {
             state,
             writer,
@@ -320,10 +586,29 @@ impl<'a, 'c> Write for TracingFileAppender<'a, 'c> {
             let _a = self.state.add_date(now, current_timestamp);
             self.state.refresh_writer(now, writer);
         }
-        writer.write(buf)
+        let written = writer.write(buf);
+        match &written {
+            Ok(n) => metrics().appender_bytes_written.inc_by(*n as u64),
+            Err(_) => metrics().appender_errors.inc(),
+        }
+        written
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.writer.get_mut().unwrap_or_else(PoisonError::into_inner).flush()
+        let flushed = self.writer.get_mut().unwrap_or_else(PoisonError::into_inner).flush();
+        if flushed.is_err() {
+            metrics().appender_errors.inc();
+        }
+        flushed
+    }
+}
+
+impl<'a, 'c> Drop for TracingFileAppender<'a, 'c> {
+    fn drop(&mut self) {
+        let writer = self.writer.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Err(err) = writer.sync() {
+            metrics().appender_errors.inc();
+            eprintln!("Couldn't sync log file: {}", err);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{get, HttpResponse};
+use anyhow::{anyhow, bail, Context};
+use migration::sea_orm::DatabaseConnection;
+use migration::{Migrator, MigratorTrait};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::logging::LogConfig;
+use crate::openapi::{object, string, Document, Operation, Schema};
+use crate::shutdown;
+
+/// 单项检查的超时，避免连接池耗尽时探针一直挂起
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Fail,
+}
+
+/// 单项检查结果
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub status: Status,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 探针响应体，任一检查失败时整体为 `fail`
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub status: Status,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Report {
+    fn new(checks: BTreeMap<&'static str, Check>) -> Self {
+        let status = if checks.values().all(|check| check.status == Status::Ok) {
+            Status::Ok
+        } else {
+            Status::Fail
+        };
+        Report { status, checks }
+    }
+
+    fn response(&self) -> HttpResponse {
+        let status = match self.status {
+            Status::Ok => StatusCode::OK,
+            Status::Fail => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        HttpResponse::build(status)
+            .insert_header((CACHE_CONTROL, "no-store"))
+            .json(self)
+    }
+}
+
+impl Schema for Report {
+    const NAME: &'static str = "HealthReport";
+
+    fn schema() -> Value {
+        let status = json!({"type": "string", "enum": ["ok", "fail"]});
+        let check = object(
+            &[("status", status.clone()), ("latency_ms", json!({"type": "number"})), ("error", string())],
+            &["status", "latency_ms"],
+        );
+        let mut schema = object(&[("status", status), ("checks", json!({"type": "object"}))], &["status", "checks"]);
+        schema["properties"]["checks"]["additionalProperties"] = check;
+        schema
+    }
+
+    fn example() -> Value {
+        json!({"status": "ok", "checks": {"database": {"status": "ok", "latency_ms": 1.2}}})
+    }
+}
+
+/// 执行一项检查并计时
+pub async fn check<F: Future<Output = Result<(), anyhow::Error>>>(future: F) -> Check {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, future).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("timed out after {:?}", CHECK_TIMEOUT)),
+    };
+    Check {
+        status: if result.is_ok() { Status::Ok } else { Status::Fail },
+        latency_ms: start.elapsed().as_micros() as f64 / 1000.0,
+        error: result.err().map(|e| format!("{:#}", e)),
+    }
+}
+
+/// 探针共享的状态
+pub struct Probes {
+    /// 写文件日志时检查目录可写
+    log_directory: Option<PathBuf>,
+    started: AtomicBool,
+}
+
+impl Probes {
+    pub fn new(log: &LogConfig) -> Self {
+        Probes {
+            log_directory: log.file.then(|| PathBuf::from(&log.directory)),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// 数据库可用、迁移已全部执行、日志目录可写，且未开始停机
+    pub async fn readiness(&self, conn: &DatabaseConnection) -> Report {
+        let mut checks = BTreeMap::new();
+        let (database, migrations) = tokio::join!(
+            check(async { Ok(conn.ping().await?) }),
+            check(pending_migrations(conn)),
+        );
+        checks.insert("database", database);
+        checks.insert("migrations", migrations);
+        if let Some(directory) = &self.log_directory {
+            checks.insert("log_directory", check(writable(directory)).await);
+        }
+        checks.insert(
+            "shutdown",
+            check(async {
+                if shutdown::is_shutting_down() {
+                    bail!("shutting down")
+                }
+                Ok(())
+            })
+            .await,
+        );
+        Report::new(checks)
+    }
+}
+
+async fn pending_migrations(conn: &DatabaseConnection) -> Result<(), anyhow::Error> {
+    let pending = Migrator::get_pending_migrations(conn).await?;
+    if !pending.is_empty() {
+        let names: Vec<_> = pending.iter().map(|migration| migration.name().to_string()).collect();
+        bail!("{} pending migration(s): {}", names.len(), names.join(", "))
+    }
+    Ok(())
+}
+
+/// 在目录中创建并删除一个临时文件，并发的探针各用一个文件名
+async fn writable(directory: &Path) -> Result<(), anyhow::Error> {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    let probe = directory.join(format!(".readyz-{}-{}", std::process::id(), seq));
+    tokio::fs::write(&probe, b"ok")
+        .await
+        .with_context(|| format!("{} is not writable", directory.display()))?;
+    tokio::fs::remove_file(&probe).await?;
+    Ok(())
+}
+
+/// 存活探针：进程能处理请求即可
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    Report::new(BTreeMap::new()).response()
+}
+
+/// 就绪探针，停机开始后返回 503
+#[get("/readyz")]
+pub async fn readyz(probes: Data<Probes>, conn: Data<DatabaseConnection>) -> HttpResponse {
+    probes.readiness(conn.get_ref()).await.response()
+}
+
+/// 启动探针：首次就绪之后一直成功，不再检查依赖
+#[get("/startupz")]
+pub async fn startupz(probes: Data<Probes>, conn: Data<DatabaseConnection>) -> HttpResponse {
+    if probes.started.load(Ordering::Acquire) {
+        return Report::new(BTreeMap::new()).response();
+    }
+    let report = probes.readiness(conn.get_ref()).await;
+    if report.status == Status::Ok {
+        probes.started.store(true, Ordering::Release);
+    }
+    report.response()
+}
+
+pub fn openapi(doc: &mut Document) {
+    let report = doc.schema::<Report>();
+    for (path, id, summary) in [
+        ("/healthz", "healthz", "存活探针"),
+        ("/readyz", "readyz", "就绪探针，停机开始后返回 503"),
+        ("/startupz", "startupz", "启动探针，首次就绪之后一直成功"),
+    ] {
+        doc.route(
+            "get",
+            path,
+            Operation::new(id, summary, "health")
+                .response("200", "检查通过", Some(report.clone()))
+                .response("503", "检查失败", Some(report.clone())),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::App;
+    use anyhow::anyhow;
+
+    use crate::health::{check, healthz, writable, Status};
+
+    #[tokio::test]
+    async fn test_check() {
+        let ok = check(async { Ok(()) }).await;
+        assert_eq!(ok.status, Status::Ok);
+        assert!(ok.error.is_none());
+        let failed = check(async { Err(anyhow!("connection refused")) }).await;
+        assert_eq!(failed.status, Status::Fail);
+        assert_eq!(failed.error.as_deref(), Some("connection refused"));
+    }
+
+    #[tokio::test]
+    async fn test_log_directory_writable() {
+        let directory = std::env::temp_dir();
+        assert!(writable(&directory).await.is_ok());
+        assert!(writable(&directory.join("readyz-missing")).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_writable() {
+        //同时执行的探针不能互相删掉对方的文件
+        let probes: Vec<_> = (0..16).map(|_| tokio::spawn(async { writable(&std::env::temp_dir()).await })).collect();
+        for probe in probes {
+            assert!(probe.await.unwrap().is_ok());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_healthz() {
+        let app = actix_web::test::init_service(App::new().service(healthz)).await;
+        let request = actix_web::test::TestRequest::get().uri("/healthz").to_request();
+        let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["status"], "ok");
+    }
+}
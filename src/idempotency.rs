@@ -0,0 +1,317 @@
+use std::future::{ready, Ready};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use actix_web::body::{to_bytes, BoxBody};
+use actix_web::dev::Payload;
+use actix_web::http::header::{HeaderName, CONTENT_TYPE, ETAG, LOCATION};
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use anyhow::anyhow;
+use futures_util::future::LocalBoxFuture;
+use migration::sea_orm::{
+    ConnectionTrait, DatabaseBackend, DatabaseConnection, DatabaseTransaction, Statement, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn, Instrument};
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::metrics::metrics;
+use crate::store::table_span;
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+/// 重放的响应带有此响应头
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// 与表结构 `varchar(255)` 一致
+const KEY_MAX_LEN: usize = 255;
+/// 每保存多少次响应清理一次过期的 key
+const PRUNE_EVERY: u64 = 256;
+/// 重放时恢复的响应头
+const STORED_HEADERS: [HeaderName; 3] = [CONTENT_TYPE, LOCATION, ETAG];
+
+/// 拿不到锁说明相同 key 的请求正在另一个事务中处理，锁在事务结束时释放
+const LOCK_SQL: &str = r#"select pg_try_advisory_xact_lock(hashtextextended($1, 0)) as "locked""#;
+
+const FIND_SQL: &str = r#"select "request_hash", "status", "headers", "body" from "idempotency_key"
+where "principal" = $1 and "key" = $2 and "expires_at" > now()"#;
+
+/// 冲突时覆盖的只会是已过期的记录，未过期的记录已由 `FIND_SQL` 重放
+const STORE_SQL: &str = r#"insert into "idempotency_key"
+    ("principal", "key", "request_hash", "status", "headers", "body", "created_at", "expires_at")
+values ($1, $2, $3, $4, $5, $6, now(), now() + make_interval(secs => $7))
+on conflict ("principal", "key") do update set
+    "request_hash" = excluded."request_hash",
+    "status" = excluded."status",
+    "headers" = excluded."headers",
+    "body" = excluded."body",
+    "created_at" = excluded."created_at",
+    "expires_at" = excluded."expires_at""#;
+
+const PRUNE_SQL: &str = r#"delete from "idempotency_key" where "expires_at" < now()"#;
+
+static STORES: AtomicU64 = AtomicU64::new(0);
+
+/// 幂等配置，从 `IDEMPOTENCY_` 前缀的环境变量读取
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    /// 保存响应的时间，秒，过期后相同的 key 视为新请求
+    pub ttl: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig { ttl: 24 * 60 * 60 }
+    }
+}
+
+/// 请求携带的 key，不同调用方的 key 互不影响
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKey {
+    pub principal: String,
+    pub key: String,
+}
+
+/// 提取 `Idempotency-Key`，用于 POST/PATCH 接口；未携带时只在事务内执行修改
+#[derive(Debug)]
+pub struct Idempotency {
+    pub key: Option<IdempotencyKey>,
+    method: String,
+    path: String,
+    ttl: u64,
+}
+
+impl FromRequest for Idempotency {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Idempotency::from_http(req).map_err(Into::into))
+    }
+}
+
+impl Idempotency {
+    fn from_http(req: &HttpRequest) -> Result<Self, AppError> {
+        let key = match req.headers().get(IDEMPOTENCY_KEY) {
+            Some(value) => {
+                let key = value
+                    .to_str()
+                    .ok()
+                    .filter(|key| !key.is_empty() && key.len() <= KEY_MAX_LEN && key.bytes().all(|b| b.is_ascii_graphic()))
+                    .ok_or_else(|| {
+                        AppError::Validation(format!(
+                            "Idempotency-Key must be 1 to {} visible ASCII characters",
+                            KEY_MAX_LEN
+                        ))
+                    })?;
+                //匿名请求共享同一个命名空间
+                let principal = req
+                    .extensions()
+                    .get::<Principal>()
+                    .map(|principal| format!("{}:{}", principal.auth_method, principal.user_id))
+                    .unwrap_or_else(|| "anonymous".to_string());
+                Some(IdempotencyKey {
+                    principal,
+                    key: key.to_string(),
+                })
+            }
+            None => None,
+        };
+        let ttl = req
+            .app_data::<Data<IdempotencyConfig>>()
+            .map(|config| config.ttl)
+            .unwrap_or_else(|| IdempotencyConfig::default().ttl);
+        Ok(Idempotency {
+            key,
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            ttl,
+        })
+    }
+
+    /// 方法、路径与请求体的 SHA-256
+    pub fn fingerprint<B: Serialize>(&self, body: &B) -> Result<String, AppError> {
+        let body = serde_json::to_vec(body).map_err(|e| anyhow!("encode request body: {}", e))?;
+        let mut digest = Sha256::new();
+        digest.update(self.method.as_bytes());
+        digest.update(b"\n");
+        digest.update(self.path.as_bytes());
+        digest.update(b"\n");
+        digest.update(&body);
+        Ok(hex::encode(digest.finalize()))
+    }
+
+    /// 在事务内执行修改，带 key 时把响应写入同一事务；已有响应时直接重放，不再执行修改
+    ///
+    /// 只保存成功的响应，修改失败时事务回滚，客户端可以用同一个 key 重试。
+    pub async fn run<B, F>(self, conn: &DatabaseConnection, body: B, mutation: F) -> Result<HttpResponse, AppError>
+    where
+        B: Serialize,
+        F: for<'c> FnOnce(&'c DatabaseTransaction, B) -> LocalBoxFuture<'c, Result<HttpResponse, AppError>>,
+    {
+        let start = Instant::now();
+        let tx = conn.begin().await?;
+        metrics().db_pool_wait.with_label_values(&["begin"]).observe(start.elapsed().as_secs_f64());
+        let Some(key) = &self.key else {
+            let response = mutation(&tx, body).await?;
+            tx.commit().await?;
+            return Ok(response);
+        };
+        let request_hash = self.fingerprint(&body)?;
+        let lock = format!("{}\n{}", key.principal, key.key);
+        let locked = tx
+            .query_one(statement(LOCK_SQL, vec![lock.into()]))
+            .instrument(table_span("idempotency_key", "lock", "lock idempotency_key"))
+            .await?
+            .map(|row| row.try_get::<bool>("", "locked"))
+            .transpose()?
+            .unwrap_or(false);
+        if !locked {
+            return Err(AppError::IdempotencyInProgress(
+                "a request with this Idempotency-Key is still in progress".to_string(),
+            ));
+        }
+        let stored = tx
+            .query_one(statement(FIND_SQL, vec![key.principal.clone().into(), key.key.clone().into()]))
+            .instrument(table_span("idempotency_key", "select", "find idempotency_key"))
+            .await?;
+        if let Some(row) = stored {
+            let stored_hash: String = row.try_get("", "request_hash")?;
+            if stored_hash != request_hash {
+                return Err(AppError::IdempotencyKeyReused(
+                    "Idempotency-Key was already used for a different request".to_string(),
+                ));
+            }
+            let status: i16 = row.try_get("", "status")?;
+            let headers: Value = row.try_get("", "headers")?;
+            let body: Vec<u8> = row.try_get("", "body")?;
+            tx.commit().await?;
+            info!(idempotency.key = %key.key, "replaying stored response");
+            return replay(status, &headers, body);
+        }
+
+        let response = mutation(&tx, body).await?;
+        let (response, body) = response.into_parts();
+        let body = to_bytes(body).await.map_err(|e| anyhow!("buffer response body: {}", e))?;
+        let headers: Map<String, Value> = STORED_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = response.headers().get(name)?.to_str().ok()?;
+                Some((name.to_string(), Value::String(value.to_string())))
+            })
+            .collect();
+        tx.execute(statement(
+            STORE_SQL,
+            vec![
+                key.principal.clone().into(),
+                key.key.clone().into(),
+                request_hash.into(),
+                (response.status().as_u16() as i16).into(),
+                Value::Object(headers).into(),
+                body.to_vec().into(),
+                (self.ttl as f64).into(),
+            ],
+        ))
+        .instrument(table_span("idempotency_key", "insert", "upsert idempotency_key"))
+        .await?;
+        tx.commit().await?;
+        if STORES.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            //修改已提交，清理失败不影响响应
+            if let Err(e) = conn
+                .execute(statement(PRUNE_SQL, vec![]))
+                .instrument(table_span("idempotency_key", "delete", "delete expired idempotency_key"))
+                .await
+            {
+                warn!("pruning expired idempotency keys failed: {}", e);
+            }
+        }
+        Ok(response.set_body(BoxBody::new(body)))
+    }
+}
+
+fn statement(sql: &str, values: Vec<migration::sea_orm::Value>) -> Statement {
+    Statement::from_sql_and_values(DatabaseBackend::Postgres, sql, values)
+}
+
+fn replay(status: i16, headers: &Value, body: Vec<u8>) -> Result<HttpResponse, AppError> {
+    let status = StatusCode::from_u16(status as u16).map_err(|e| anyhow!("stored status {}: {}", status, e))?;
+    let mut response = HttpResponse::build(status);
+    if let Some(headers) = headers.as_object() {
+        for (name, value) in headers {
+            if let Some(value) = value.as_str() {
+                response.insert_header((name.as_str(), value));
+            }
+        }
+    }
+    response.insert_header((IDEMPOTENT_REPLAYED, "true"));
+    Ok(response.body(body))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::test::TestRequest;
+    use actix_web::HttpMessage;
+    use serde_json::json;
+
+    use crate::auth::{AuthMethod, Principal};
+    use crate::idempotency::{replay, Idempotency, IdempotencyKey};
+
+    #[test]
+    fn test_key_header() -> Result<(), anyhow::Error> {
+        let idempotency = Idempotency::from_http(&TestRequest::post().uri("/countries").to_http_request())?;
+        assert!(idempotency.key.is_none());
+
+        let request = TestRequest::post()
+            .uri("/countries")
+            .insert_header(("idempotency-key", "8e03978e-40d5-43e8-bc93-6894a57f9324"))
+            .to_http_request();
+        request.extensions_mut().insert(Principal {
+            user_id: "42".to_string(),
+            tenant: None,
+            auth_method: AuthMethod::Gateway,
+            roles: vec![],
+        });
+        let key = Idempotency::from_http(&request)?.key;
+        assert_eq!(
+            key,
+            Some(IdempotencyKey {
+                principal: "gateway:42".to_string(),
+                key: "8e03978e-40d5-43e8-bc93-6894a57f9324".to_string(),
+            })
+        );
+
+        for invalid in ["", "has space", &"k".repeat(256)] {
+            let request = TestRequest::post().insert_header(("idempotency-key", invalid)).to_http_request();
+            assert!(Idempotency::from_http(&request).is_err(), "{:?}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fingerprint() -> Result<(), anyhow::Error> {
+        let idempotency = |path: &str| Idempotency::from_http(&TestRequest::post().uri(path).to_http_request());
+        let increment = idempotency("/countries/1/increment")?;
+        let same = increment.fingerprint(&json!({"amount": 1}))?;
+        assert_eq!(same, idempotency("/countries/1/increment")?.fingerprint(&json!({"amount": 1}))?);
+        assert_ne!(same, increment.fingerprint(&json!({"amount": 2}))?);
+        assert_ne!(same, idempotency("/countries/2/increment")?.fingerprint(&json!({"amount": 1}))?);
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_replay() -> Result<(), anyhow::Error> {
+        let headers = json!({"content-type": "application/json", "location": "/countries/7"});
+        let response = replay(201, &headers, br#"{"id":7}"#.to_vec()).map_err(|e| anyhow::anyhow!("{}", e))?;
+        assert_eq!(response.status().as_u16(), 201);
+        assert_eq!(response.headers().get("location").unwrap(), "/countries/7");
+        assert_eq!(response.headers().get("idempotent-replayed").unwrap(), "true");
+        let body = actix_web::body::to_bytes(response.into_body()).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+        assert_eq!(&body[..], br#"{"id":7}"#);
+        Ok(())
+    }
+}
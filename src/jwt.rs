@@ -0,0 +1,294 @@
+use anyhow::{bail, Context};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use rsa::RsaPublicKey;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::Sha256;
+
+use crate::auth::{AuthConfig, Role};
+use crate::error::AppError;
+
+/// HS256 密钥的最小长度，与 SHA-256 输出长度一致（RFC 7518 3.2）
+const HS256_MIN_KEY_LEN: usize = 32;
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(value) => value == audience,
+            Audience::Many(values) => values.iter().any(|value| value == audience),
+        }
+    }
+}
+
+/// 校验通过的 token 中用到的声明
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: i64,
+    pub nbf: Option<i64>,
+    pub iss: Option<String>,
+    aud: Option<Audience>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl Claims {
+    /// 角色声明可以是字符串数组，或以空格、逗号分隔的字符串，未知角色忽略
+    pub fn roles(&self, claim: &str) -> Vec<Role> {
+        let names: Vec<&str> = match self.extra.get(claim) {
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(value)) => value.split([' ', ',']).collect(),
+            _ => vec![],
+        };
+        names.into_iter().filter_map(|name| name.trim().parse().ok()).collect()
+    }
+
+    pub fn string(&self, claim: &str) -> Option<String> {
+        self.extra.get(claim).and_then(Value::as_str).map(str::to_string)
+    }
+}
+
+/// JWT 校验，支持 HS256 与 RS256，密钥在启动时从本地文件读取
+pub struct JwtVerifier {
+    hs256: Option<Vec<u8>>,
+    rs256: Option<VerifyingKey<Sha256>>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: i64,
+}
+
+impl JwtVerifier {
+    /// 两种密钥都未配置时返回 `None`，不接受 Bearer token
+    pub fn load(config: &AuthConfig) -> Result<Option<Self>, anyhow::Error> {
+        let hs256 = match &config.jwt_secret_file {
+            Some(path) => {
+                let secret = std::fs::read(path).with_context(|| format!("cannot read AUTH_JWT_SECRET_FILE {}", path))?;
+                //去掉 echo 等工具写入的换行
+                let secret = secret.trim_ascii_end().to_vec();
+                if secret.len() < HS256_MIN_KEY_LEN {
+                    bail!("AUTH_JWT_SECRET_FILE must contain at least {} bytes", HS256_MIN_KEY_LEN)
+                }
+                Some(secret)
+            }
+            None => None,
+        };
+        let rs256 = match &config.jwt_public_key_file {
+            Some(path) => {
+                let pem = std::fs::read_to_string(path)
+                    .with_context(|| format!("cannot read AUTH_JWT_PUBLIC_KEY_FILE {}", path))?;
+                Some(VerifyingKey::new(parse_public_key(&pem).with_context(|| format!("invalid RSA public key {}", path))?))
+            }
+            None => None,
+        };
+        if hs256.is_none() && rs256.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(JwtVerifier {
+            hs256,
+            rs256,
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+            leeway: config.jwt_leeway as i64,
+        }))
+    }
+
+    /// 校验签名与 `exp`、`nbf`、`iss`、`aud`，`now` 为 unix 秒
+    pub fn verify(&self, token: &str, now: i64) -> Result<Claims, AppError> {
+        let invalid = |reason: &str| AppError::Unauthorized(format!("invalid token: {}", reason));
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("malformed"));
+        };
+        //签名覆盖 header 与 payload 的原始编码
+        let message = &token[..header.len() + 1 + payload.len()];
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid("malformed"))?;
+        let header: Header = decode_part(header).ok_or_else(|| invalid("malformed"))?;
+        let verified = match header.alg.as_str() {
+            "HS256" => {
+                let secret = self.hs256.as_ref().ok_or_else(|| invalid("HS256 is not accepted"))?;
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(|_| invalid("HS256 is not accepted"))?;
+                mac.update(message.as_bytes());
+                mac.verify_slice(&signature).is_ok()
+            }
+            "RS256" => {
+                let key = self.rs256.as_ref().ok_or_else(|| invalid("RS256 is not accepted"))?;
+                Signature::try_from(signature.as_slice())
+                    .map(|signature| key.verify(message.as_bytes(), &signature).is_ok())
+                    .unwrap_or(false)
+            }
+            alg => return Err(invalid(&format!("unsupported alg {}", alg))),
+        };
+        if !verified {
+            return Err(invalid("bad signature"));
+        }
+        let claims: Claims = decode_part(payload).ok_or_else(|| invalid("malformed claims"))?;
+        if claims.exp + self.leeway <= now {
+            return Err(invalid("expired"));
+        }
+        if claims.nbf.is_some_and(|nbf| nbf - self.leeway > now) {
+            return Err(invalid("not yet valid"));
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.iss.as_ref() != Some(issuer) {
+                return Err(invalid("unexpected issuer"));
+            }
+        }
+        if let Some(audience) = &self.audience {
+            if !claims.aud.as_ref().is_some_and(|aud| aud.contains(audience)) {
+                return Err(invalid("unexpected audience"));
+            }
+        }
+        Ok(claims)
+    }
+}
+
+fn decode_part<T: DeserializeOwned>(part: &str) -> Option<T> {
+    let bytes = URL_SAFE_NO_PAD.decode(part).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// 接受 `BEGIN PUBLIC KEY`（SPKI）与 `BEGIN RSA PUBLIC KEY`（PKCS#1）两种 PEM
+fn parse_public_key(pem: &str) -> Result<RsaPublicKey, anyhow::Error> {
+    match RsaPublicKey::from_public_key_pem(pem) {
+        Ok(key) => Ok(key),
+        Err(_) => Ok(RsaPublicKey::from_pkcs1_pem(pem)?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use hmac::{Hmac, Mac};
+    use rsa::pkcs1v15::{SigningKey, VerifyingKey};
+    use rsa::pkcs8::DecodePrivateKey;
+    use rsa::signature::{SignatureEncoding, Signer};
+    use rsa::RsaPrivateKey;
+    use serde_json::{json, Value};
+    use sha2::Sha256;
+
+    use crate::auth::{AuthConfig, Role};
+    use crate::jwt::{parse_public_key, JwtVerifier};
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+    const NOW: i64 = 1_700_000_000;
+
+    fn encode(value: &Value) -> String {
+        URL_SAFE_NO_PAD.encode(value.to_string())
+    }
+
+    fn hs256(header: Value, claims: Value, secret: &[u8]) -> String {
+        let message = format!("{}.{}", encode(&header), encode(&claims));
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(message.as_bytes());
+        format!("{}.{}", message, URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    fn verifier() -> JwtVerifier {
+        JwtVerifier {
+            hs256: Some(SECRET.to_vec()),
+            rs256: None,
+            issuer: Some("https://issuer.example".to_string()),
+            audience: Some("tokio-learn".to_string()),
+            leeway: 60,
+        }
+    }
+
+    fn claims() -> Value {
+        json!({
+            "sub": "alice",
+            "exp": NOW + 300,
+            "iss": "https://issuer.example",
+            "aud": ["other", "tokio-learn"],
+            "roles": ["writer", "unknown"],
+            "tenant": "acme",
+        })
+    }
+
+    #[test]
+    fn test_hs256() -> Result<(), anyhow::Error> {
+        let verifier = verifier();
+        let header = json!({"alg": "HS256", "typ": "JWT"});
+        let verified = verifier.verify(&hs256(header.clone(), claims(), SECRET), NOW)?;
+        assert_eq!(verified.sub, "alice");
+        assert_eq!(verified.roles("roles"), vec![Role::Writer]);
+        assert_eq!(verified.string("tenant").as_deref(), Some("acme"));
+
+        let reject = |token: String| verifier.verify(&token, NOW).unwrap_err().to_string();
+        assert_eq!(reject(hs256(header.clone(), claims(), b"another secret of at least 32 bytes")), "invalid token: bad signature");
+        let mut expired = claims();
+        expired["exp"] = json!(NOW - 61);
+        assert_eq!(reject(hs256(header.clone(), expired, SECRET)), "invalid token: expired");
+        let mut early = claims();
+        early["nbf"] = json!(NOW + 120);
+        assert_eq!(reject(hs256(header.clone(), early, SECRET)), "invalid token: not yet valid");
+        let mut issuer = claims();
+        issuer["iss"] = json!("https://evil.example");
+        assert_eq!(reject(hs256(header.clone(), issuer, SECRET)), "invalid token: unexpected issuer");
+        let mut audience = claims();
+        audience["aud"] = json!("other");
+        assert_eq!(reject(hs256(header.clone(), audience, SECRET)), "invalid token: unexpected audience");
+        //不接受 alg=none 与未配置密钥的算法
+        let none = format!("{}.{}.", encode(&json!({"alg": "none"})), encode(&claims()));
+        assert_eq!(reject(none), "invalid token: unsupported alg none");
+        assert_eq!(reject(hs256(json!({"alg": "RS256"}), claims(), SECRET)), "invalid token: RS256 is not accepted");
+        assert_eq!(reject("not-a-token".to_string()), "invalid token: malformed");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rs256() -> Result<(), anyhow::Error> {
+        let private_key = RsaPrivateKey::from_pkcs8_pem(include_str!("testdata/jwt_rs256_private.pem"))?;
+        let public_key = parse_public_key(include_str!("testdata/jwt_rs256_public.pem"))?;
+        let verifier = JwtVerifier {
+            hs256: None,
+            rs256: Some(VerifyingKey::new(public_key)),
+            ..verifier()
+        };
+        let message = format!("{}.{}", encode(&json!({"alg": "RS256"})), encode(&claims()));
+        let signature = SigningKey::<Sha256>::new(private_key).sign(message.as_bytes());
+        let token = format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature.to_bytes()));
+        assert_eq!(verifier.verify(&token, NOW)?.sub, "alice");
+        //RS256 公钥不能被当作 HS256 密钥使用
+        let forged = hs256(json!({"alg": "HS256"}), claims(), include_bytes!("testdata/jwt_rs256_public.pem"));
+        assert_eq!(verifier.verify(&forged, NOW).unwrap_err().to_string(), "invalid token: HS256 is not accepted");
+        Ok(())
+    }
+
+    #[test]
+    fn test_load() -> Result<(), anyhow::Error> {
+        assert!(JwtVerifier::load(&AuthConfig::default())?.is_none());
+        let path = std::env::temp_dir().join(format!("jwt-secret-{}", std::process::id()));
+        std::fs::write(&path, "too short\n")?;
+        let config = AuthConfig {
+            jwt_secret_file: Some(path.display().to_string()),
+            ..Default::default()
+        };
+        assert!(JwtVerifier::load(&config).is_err());
+        std::fs::write(&path, [SECRET, b"\n"].concat())?;
+        let verifier = JwtVerifier::load(&config)?.expect("verifier");
+        assert_eq!(verifier.hs256.as_deref(), Some(SECRET));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use actix_web::web::Bytes;
+use futures_util::Stream;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::openapi::{described, object, string, Schema};
+
+/// 内存中保留的最近日志条数
+pub const DEFAULT_CAPACITY: usize = 5000;
+/// `time` 字段的格式，本地时间精确到毫秒
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogRecord {
+    pub time: String,
+    #[serde(serialize_with = "serialize_level")]
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    pub line: String,
+}
+
+fn serialize_level<S: Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(level.as_str())
+}
+
+/// 查询过滤条件，`level` 为最低级别（如 `warn` 同时返回 WARN 与 ERROR），`target` 按前缀匹配
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct LogFilter {
+    pub level: Option<String>,
+    pub target: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl Schema for LogRecord {
+    const NAME: &'static str = "LogRecord";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("time", described(string(), "本地时间，格式 `YYYY-MM-DD HH:MM:SS.mmm`")),
+                ("level", json!({"type": "string", "enum": ["TRACE", "DEBUG", "INFO", "WARN", "ERROR"]})),
+                ("target", string()),
+                ("message", string()),
+                ("line", described(string(), "格式化后的整行日志")),
+            ],
+            &["time", "level", "target", "message", "line"],
+        )
+    }
+
+    fn example() -> Value {
+        json!({
+            "time": "2024-05-01 08:00:00.000",
+            "level": "INFO",
+            "target": "tokio_learn",
+            "message": "hello",
+            "line": "2024-05-01 08:00:00.000  INFO tokio_learn: hello",
+        })
+    }
+}
+
+impl Schema for LogFilter {
+    const NAME: &'static str = "LogFilter";
+
+    fn schema() -> Value {
+        object(
+            &[
+                ("level", described(json!({"type": "string", "enum": ["trace", "debug", "info", "warn", "error"]}), "最低级别")),
+                ("target", described(string(), "按前缀匹配")),
+                ("limit", json!({"type": "integer", "minimum": 0})),
+            ],
+            &[],
+        )
+    }
+
+    fn example() -> Value {
+        json!({"level": "warn", "target": "tokio_learn", "limit": 100})
+    }
+}
+
+impl LogFilter {
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(level) = &self.level {
+            Level::from_str(level).map_err(|_| anyhow::anyhow!("unknown level: {}", level))?;
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        let level_ok = self
+            .level
+            .as_deref()
+            .and_then(|level| Level::from_str(level).ok())
+            .map(|level| record.level <= level)
+            .unwrap_or(true);
+        let target_ok = self
+            .target
+            .as_deref()
+            .map(|target| record.target.starts_with(target))
+            .unwrap_or(true);
+        level_ok && target_ok
+    }
+}
+
+/// 有界环形缓冲区，保存最近格式化后的日志事件，并广播给实时订阅者
+#[derive(Clone)]
+pub struct LogBuffer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    capacity: usize,
+    records: Mutex<VecDeque<LogRecord>>,
+    sender: broadcast::Sender<LogRecord>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(1024);
+        LogBuffer {
+            inner: Arc::new(Inner {
+                capacity,
+                records: Mutex::new(VecDeque::with_capacity(capacity)),
+                sender,
+            }),
+        }
+    }
+
+    pub fn layer(&self) -> LogBufferLayer {
+        LogBufferLayer {
+            buffer: self.clone(),
+        }
+    }
+
+    pub fn push(&self, record: LogRecord) {
+        {
+            let mut records = self.inner.records.lock().unwrap_or_else(PoisonError::into_inner);
+            if records.len() >= self.inner.capacity {
+                records.pop_front();
+            }
+            records.push_back(record.clone());
+        }
+        //没有订阅者时发送会失败，忽略即可
+        let _ = self.inner.sender.send(record);
+    }
+
+    /// 按时间顺序返回匹配的日志，`limit` 限制返回最新的条数
+    pub fn recent(&self, filter: &LogFilter) -> Vec<LogRecord> {
+        let records = self.inner.records.lock().unwrap_or_else(PoisonError::into_inner);
+        let limit = filter.limit.unwrap_or(self.inner.capacity);
+        let mut matched: Vec<LogRecord> = records
+            .iter()
+            .rev()
+            .filter(|record| filter.matches(record))
+            .take(limit)
+            .cloned()
+            .collect();
+        matched.reverse();
+        matched
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogRecord> {
+        self.inner.sender.subscribe()
+    }
+
+    /// Server-Sent Events 流，每条日志为一个 `log` 事件
+    pub fn sse_stream(&self, filter: LogFilter) -> impl Stream<Item=Result<Bytes, actix_web::Error>> {
+        let receiver = self.subscribe();
+        futures_util::stream::unfold((receiver, filter), |(mut receiver, filter)| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(record) if filter.matches(&record) => {
+                        let data = serde_json::to_string(&record).unwrap_or_default();
+                        let chunk = Bytes::from(format!("event: log\ndata: {}\n\n", data));
+                        return Some((Ok(chunk), (receiver, filter)));
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        let chunk = Bytes::from(format!(": lagged {}\n\n", skipped));
+                        return Some((Ok(chunk), (receiver, filter)));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+pub struct LogBufferLayer {
+    buffer: LogBuffer,
+}
+
+impl<S> Layer<S> for LogBufferLayer
+    where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        let time = chrono::Local::now().format(TIME_FORMAT).to_string();
+        let mut line = format!("{} {:>5} ", time, metadata.level());
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let _ = write!(line, "{}:", span.name());
+            }
+            line.push(' ');
+        }
+        let _ = write!(line, "{}: {}{}", metadata.target(), visitor.message, visitor.fields);
+
+        self.buffer.push(LogRecord {
+            time,
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+            line,
+        });
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tracing::{info, warn};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    use crate::log_buffer::{LogBuffer, LogFilter, LogRecord, TIME_FORMAT};
+    use crate::openapi::Schema;
+
+    #[test]
+    fn test_capacity_drops_oldest() {
+        let buffer = LogBuffer::new(2);
+        let subscriber = Registry::default().with(buffer.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            info!("one");
+            info!("two");
+            info!("three");
+        });
+        let records = buffer.recent(&LogFilter::default());
+        let messages: Vec<&str> = records.iter().map(|r| r.message.as_str()).collect();
+        assert_eq!(messages, vec!["two", "three"]);
+    }
+
+    #[test]
+    fn test_level_and_target_filter() -> Result<(), anyhow::Error> {
+        let buffer = LogBuffer::new(10);
+        let subscriber = Registry::default().with(buffer.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            info!(target: "store", id = 1, "info");
+            warn!(target: "store::lock", "warn");
+            warn!(target: "router", "other");
+        });
+        let filter = LogFilter {
+            level: Some("warn".to_string()),
+            target: Some("store".to_string()),
+            limit: None,
+        };
+        filter.validate()?;
+        let records = buffer.recent(&filter);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].target, "store::lock");
+
+        let all = buffer.recent(&LogFilter { limit: Some(1), ..Default::default() });
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].message, "other");
+        assert!(buffer.recent(&LogFilter::default())[0].line.ends_with("store: info id=1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_level() {
+        let filter = LogFilter {
+            level: Some("loud".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.validate().is_err());
+    }
+
+    #[test]
+    fn test_subscribe_receives_new_records() {
+        let buffer = LogBuffer::new(10);
+        let mut receiver = buffer.subscribe();
+        let subscriber = Registry::default().with(buffer.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            info!("live");
+        });
+        assert_eq!(receiver.try_recv().unwrap().message, "live");
+    }
+
+    #[test]
+    fn test_example_matches_output() -> Result<(), anyhow::Error> {
+        let buffer = LogBuffer::new(1);
+        let subscriber = Registry::default().with(buffer.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            info!(target: "tokio_learn", "hello");
+        });
+        let record = serde_json::to_value(&buffer.recent(&LogFilter::default())[0])?;
+        let example = LogRecord::example();
+        for value in [&record, &example] {
+            let time = value["time"].as_str().unwrap_or_default();
+            chrono::NaiveDateTime::parse_from_str(time, TIME_FORMAT)?;
+            assert_eq!(time.len(), "2024-05-01 08:00:00.000".len());
+        }
+        let line = |value: &serde_json::Value| value["line"].as_str().unwrap_or_default()[23..].to_string();
+        assert_eq!(line(&record), line(&example));
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// 文件段头部魔数
+pub const MAGIC: &[u8; 6] = b"TLENC1";
+/// 明文累计到该大小时封装为一个块，flush 时也会封装
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+const SEGMENT: u8 = b'H';
+const CHUNK: u8 = b'C';
+const PREFIX_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+/// 32 字节 ChaCha20-Poly1305 密钥，配置中以 64 位十六进制字符串提供
+#[derive(Clone)]
+pub struct EncryptionKey(Key);
+
+impl EncryptionKey {
+    pub fn from_hex(value: &str) -> Result<Self, anyhow::Error> {
+        let bytes = hex::decode(value.trim()).map_err(|e| anyhow!("invalid log encryption key: {}", e))?;
+        if bytes.len() != 32 {
+            bail!("invalid log encryption key: expected 32 bytes, got {}", bytes.len())
+        }
+        Ok(EncryptionKey(*Key::from_slice(&bytes)))
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(&self.0)
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(***)")
+    }
+}
+
+/// 流式加密写入器
+///
+/// 文件由若干记录组成：段头 `H` + 魔数 + 8 字节随机前缀，数据块 `C` + u32 长度 + 密文。
+/// 每块 nonce 为段前缀 + 块序号，进程崩溃最多丢失尚未封装的最后一块；
+/// 重新打开已有文件时会截掉不完整的尾部记录并开始新的段，因此每个滚动文件都可以单独解密。
+pub struct EncryptedWriter<W: Write> {
+    inner: W,
+    cipher: ChaCha20Poly1305,
+    prefix: [u8; PREFIX_LEN],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptedWriter<W> {
+    pub fn new(mut inner: W, key: &EncryptionKey) -> std::io::Result<Self> {
+        let prefix = write_segment(&mut inner)?;
+        Ok(EncryptedWriter {
+            inner,
+            cipher: key.cipher(),
+            prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    fn seal(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if self.counter == u32::MAX {
+            self.prefix = write_segment(&mut self.inner)?;
+            self.counter = 0;
+        }
+        let nonce = nonce(&self.prefix, self.counter);
+        let ciphertext = self.cipher
+            .encrypt(&nonce, self.buffer.as_slice())
+            .map_err(|_| std::io::Error::other("log chunk encryption failed"))?;
+        let mut record = Vec::with_capacity(ciphertext.len() + 5);
+        record.push(CHUNK);
+        record.extend_from_slice(&(ciphertext.len() as u32).to_le_bytes());
+        record.extend_from_slice(&ciphertext);
+        //整条记录一次写入，减少崩溃时出现半条记录的概率
+        self.inner.write_all(&record)?;
+        self.counter += 1;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.seal()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.seal()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for EncryptedWriter<W> {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            eprintln!("Couldn't flush encrypted log chunk: {}", err);
+        }
+    }
+}
+
+fn write_segment<W: Write>(inner: &mut W) -> std::io::Result<[u8; PREFIX_LEN]> {
+    let mut prefix = [0u8; PREFIX_LEN];
+    OsRng.fill_bytes(&mut prefix);
+    let mut header = Vec::with_capacity(1 + MAGIC.len() + PREFIX_LEN);
+    header.push(SEGMENT);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&prefix);
+    inner.write_all(&header)?;
+    Ok(prefix)
+}
+
+fn nonce(prefix: &[u8; PREFIX_LEN], counter: u32) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..].copy_from_slice(&counter.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+/// 截掉崩溃留下的不完整尾部记录，返回截断后的文件长度
+pub fn repair_tail(file: &mut File) -> Result<u64, anyhow::Error> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(&mut *file);
+    let mut valid = 0u64;
+    loop {
+        let mut kind = [0u8; 1];
+        if reader.read(&mut kind)? == 0 {
+            break;
+        }
+        let size = match kind[0] {
+            SEGMENT => (MAGIC.len() + PREFIX_LEN) as u64,
+            CHUNK => {
+                let mut size = [0u8; 4];
+                if reader.read_exact(&mut size).is_err() {
+                    break;
+                }
+                4 + u32::from_le_bytes(size) as u64
+            }
+            _ => bail!("not an encrypted log file"),
+        };
+        if valid + 1 + size > len {
+            break;
+        }
+        if kind[0] == CHUNK {
+            reader.seek_relative(size as i64 - 4)?;
+        } else {
+            reader.seek_relative(size as i64)?;
+        }
+        valid += 1 + size;
+    }
+    drop(reader);
+    if valid < len {
+        file.set_len(valid)?;
+    }
+    file.seek(SeekFrom::End(0))?;
+    Ok(valid)
+}
+
+/// 判断文件是否为加密日志
+pub fn is_encrypted(path: &Path) -> Result<bool, anyhow::Error> {
+    let mut header = [0u8; 7];
+    let mut file = File::open(path)?;
+    let size = file.read(&mut header)?;
+    Ok(size == header.len() && header[0] == SEGMENT && &header[1..] == MAGIC)
+}
+
+/// 解密整个文件，尾部不完整的块会被忽略
+pub fn decrypt<R: Read>(mut reader: R, key: &EncryptionKey) -> Result<Vec<u8>, anyhow::Error> {
+    let cipher = key.cipher();
+    let mut plaintext = Vec::new();
+    let mut prefix: Option<[u8; PREFIX_LEN]> = None;
+    let mut counter = 0u32;
+    loop {
+        let mut kind = [0u8; 1];
+        if reader.read(&mut kind)? == 0 {
+            break;
+        }
+        match kind[0] {
+            SEGMENT => {
+                let mut header = [0u8; 6 + PREFIX_LEN];
+                if reader.read_exact(&mut header).is_err() {
+                    break;
+                }
+                if &header[..6] != MAGIC {
+                    bail!("invalid encrypted log segment header")
+                }
+                let mut next = [0u8; PREFIX_LEN];
+                next.copy_from_slice(&header[6..]);
+                prefix = Some(next);
+                counter = 0;
+            }
+            CHUNK => {
+                let prefix = prefix.ok_or(anyhow!("encrypted log chunk before segment header"))?;
+                let mut size = [0u8; 4];
+                if reader.read_exact(&mut size).is_err() {
+                    break;
+                }
+                let size = u32::from_le_bytes(size) as usize;
+                if size < TAG_LEN {
+                    bail!("invalid encrypted log chunk length {}", size)
+                }
+                let mut ciphertext = vec![0u8; size];
+                if reader.read_exact(&mut ciphertext).is_err() {
+                    break;
+                }
+                let chunk = cipher
+                    .decrypt(&nonce(&prefix, counter), ciphertext.as_slice())
+                    .map_err(|_| anyhow!("encrypted log chunk {} failed authentication", counter))?;
+                plaintext.extend_from_slice(&chunk);
+                counter += 1;
+            }
+            _ => bail!("not an encrypted log file"),
+        }
+    }
+    Ok(plaintext)
+}
+
+/// 日志检索工具的入口：按行读取日志文件，加密文件使用 `key` 透明解密
+pub fn open_log(path: &Path, key: Option<&EncryptionKey>) -> Result<Box<dyn BufRead>, anyhow::Error> {
+    if is_encrypted(path)? {
+        let key = key.ok_or(anyhow!("{} is encrypted but no key was supplied", path.display()))?;
+        let plaintext = decrypt(BufReader::new(File::open(path)?), key)?;
+        return Ok(Box::new(Cursor::new(plaintext)));
+    }
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::OpenOptions;
+    use std::io::{BufRead, Write};
+
+    use crate::log_crypto::{decrypt, open_log, repair_tail, EncryptedWriter, EncryptionKey};
+
+    fn key() -> EncryptionKey {
+        EncryptionKey::from_hex(&"11".repeat(32)).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), anyhow::Error> {
+        let mut file = Vec::new();
+        {
+            let mut writer = EncryptedWriter::new(&mut file, &key())?;
+            writer.write_all(b"first\n")?;
+            writer.flush()?;
+            writer.write_all(b"second\n")?;
+        }
+        assert!(!file.windows(5).any(|w| w == b"first"));
+        assert_eq!(decrypt(file.as_slice(), &key())?, b"first\nsecond\n");
+
+        let other = EncryptionKey::from_hex(&"22".repeat(32))?;
+        assert!(decrypt(file.as_slice(), &other).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_tail_loses_only_last_chunk() -> Result<(), anyhow::Error> {
+        let mut file = Vec::new();
+        {
+            let mut writer = EncryptedWriter::new(&mut file, &key())?;
+            writer.write_all(b"kept\n")?;
+            writer.flush()?;
+            writer.write_all(b"lost\n")?;
+        }
+        file.truncate(file.len() - 3);
+        assert_eq!(decrypt(file.as_slice(), &key())?, b"kept\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_after_crash() -> Result<(), anyhow::Error> {
+        let path = std::env::temp_dir().join(format!("encrypted-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let mut writer = EncryptedWriter::new(file, &key())?;
+            writer.write_all(b"before crash\n")?;
+        }
+        //模拟写到一半崩溃
+        OpenOptions::new().append(true).open(&path)?.write_all(b"C\xff\xff")?;
+        {
+            let mut file = OpenOptions::new().read(true).append(true).open(&path)?;
+            repair_tail(&mut file)?;
+            let mut writer = EncryptedWriter::new(file, &key())?;
+            writer.write_all(b"after restart\n")?;
+        }
+        let lines: Vec<String> = open_log(&path, Some(&key()))?.lines().collect::<Result<_, _>>()?;
+        assert_eq!(lines, vec!["before crash", "after restart"]);
+        assert!(open_log(&path, None).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(EncryptionKey::from_hex("abcd").is_err());
+        assert!(EncryptionKey::from_hex(&"zz".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,562 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::bail;
+use derive_builder::Builder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use serde_json::json;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tracing::instrument::WithSubscriber;
+use tracing::subscriber::NoSubscriber;
+
+use crate::common::{async_retry, RetryPolicy};
+
+/// 请求体格式
+#[derive(Debug, Clone, Default)]
+pub enum ShipperFormat {
+    /// 每行一条 JSON：`{"message": ...}`
+    #[default]
+    Lines,
+    /// Elasticsearch `_bulk` 格式
+    ElasticBulk { index: String },
+    /// Loki `/loki/api/v1/push` 格式
+    Loki { labels: Vec<(String, String)> },
+}
+
+#[derive(Debug, Clone, Builder)]
+#[builder(setter(into), build_fn(validate = "Self::validate"))]
+pub struct Shipper {
+    endpoint: String,
+    #[builder(default)]
+    format: ShipperFormat,
+    #[builder(default = "500")]
+    batch_size: usize,
+    #[builder(default = "Duration::from_secs(1)")]
+    flush_interval: Duration,
+    /// 缺省使用全局的 [`RetryPolicy`]，配置重载后对之后的批次生效
+    #[builder(default, setter(strip_option))]
+    max_retry: Option<usize>,
+    #[builder(default, setter(strip_option))]
+    retry_delay: Option<Duration>,
+    #[builder(default = "10_000")]
+    queue_size: usize,
+    #[builder(default = "1000")]
+    max_spool_files: usize,
+    /// guard 释放时等待后台线程退出的最长时间
+    #[builder(default = "Duration::from_secs(5)")]
+    shutdown_timeout: Duration,
+}
+
+impl ShipperBuilder {
+    fn validate(&self) -> Result<(), String> {
+        if self.max_spool_files == Some(0) {
+            return Err("max_spool_files must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+type Line = (u128, String);
+
+/// 作为 `Write` 目标使用，日志行进入队列后由后台线程批量压缩发送
+pub struct LogShipper {
+    sender: mpsc::Sender<Line>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// 与 `tracing_appender::non_blocking` 的 guard 相同，drop 时发送剩余日志并等待后台线程退出
+///
+/// 停止信号走单独的 oneshot，队列已满时也不会丢失
+pub struct ShipperGuard {
+    shutdown: Option<oneshot::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+    timeout: Duration,
+}
+
+impl LogShipper {
+    /// 日志服务不可用时，批次会被写入 `<directory>/spool`，恢复后按顺序重放
+    pub fn from_builder<T: AsRef<Path>>(builder: ShipperBuilder, directory: T) -> Result<(Self, ShipperGuard), anyhow::Error> {
+        let config = builder.build()?;
+        let spool = Spool::new(directory.as_ref().join("spool"), config.max_spool_files)?;
+        let (sender, receiver) = mpsc::channel(config.queue_size);
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+        let timeout = config.shutdown_timeout;
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let worker = Worker {
+            client: reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?,
+            config,
+            spool,
+        };
+        let handle = std::thread::Builder::new()
+            .name("log-shipper".to_string())
+            .spawn(move || {
+                //发送过程中产生的日志不能再回到 shipper，否则会形成循环
+                runtime.block_on(worker.run(receiver, shutdown_receiver).with_subscriber(NoSubscriber::default()))
+            })?;
+        Ok((
+            LogShipper {
+                sender,
+                dropped: Arc::new(AtomicU64::new(0)),
+            },
+            ShipperGuard {
+                shutdown: Some(shutdown),
+                handle: Some(handle),
+                timeout,
+            },
+        ))
+    }
+
+    /// 队列满时被丢弃的日志行数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Write for LogShipper {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let line = String::from_utf8_lossy(buf).trim_end().to_string();
+        match self.sender.try_send((now_nanos(), line)) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Closed(_)) => {
+                return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "log shipper stopped"));
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ShipperGuard {
+    /// 日志服务迟迟不响应时最多等待 `shutdown_timeout`，之后放弃等待，剩余日志丢失
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        let deadline = Instant::now() + self.timeout;
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                eprintln!("Log shipper didn't stop within {:?}", self.timeout);
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if handle.join().is_err() {
+            eprintln!("Log shipper thread panicked");
+        }
+    }
+}
+
+struct Worker {
+    client: reqwest::Client,
+    config: Shipper,
+    spool: Spool,
+}
+
+impl Worker {
+    async fn run(self, mut receiver: mpsc::Receiver<Line>, mut shutdown: oneshot::Receiver<()>) {
+        let mut batch: Vec<Line> = Vec::with_capacity(self.config.batch_size);
+        let mut interval = tokio::time::interval(self.config.flush_interval);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                line = receiver.recv() => match line {
+                    Some(line) => {
+                        batch.push(line);
+                        if batch.len() >= self.config.batch_size {
+                            self.flush(&mut batch).await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = interval.tick() => {
+                    if batch.is_empty() {
+                        self.replay().await;
+                    } else {
+                        self.flush(&mut batch).await;
+                    }
+                }
+            }
+        }
+        while let Ok(line) = receiver.try_recv() {
+            batch.push(line);
+        }
+        if !batch.is_empty() {
+            self.flush(&mut batch).await;
+        }
+    }
+
+    async fn flush(&self, batch: &mut Vec<Line>) {
+        let body = match encode(&self.config.format, batch).and_then(|body| gzip(&body)) {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("Couldn't encode log batch: {}", e);
+                batch.clear();
+                return;
+            }
+        };
+        batch.clear();
+        //积压未清空时当前批次排在其后，保证接收端按时间顺序收到
+        if !self.replay().await {
+            self.spool_batch(&body);
+            return;
+        }
+        let policy = RetryPolicy::current();
+        let result = async_retry(
+            || self.send(body.clone()),
+            self.config.max_retry.unwrap_or(policy.max_retry),
+            self.config.retry_delay.unwrap_or(Duration::from_millis(policy.delay)),
+        ).await;
+        if let Err(e) = result {
+            eprintln!("Couldn't ship logs, spooling batch: {}", e);
+            self.spool_batch(&body);
+        }
+    }
+
+    fn spool_batch(&self, body: &[u8]) {
+        if let Err(e) = self.spool.push(body) {
+            eprintln!("Couldn't spool logs: {}", e);
+        }
+    }
+
+    /// 按时间顺序重放积压文件，遇到失败即停止，等下次再试；返回积压是否已清空
+    async fn replay(&self) -> bool {
+        for path in self.spool.list() {
+            let body = match fs::read(&path) {
+                Ok(body) => body,
+                Err(e) => {
+                    eprintln!("Couldn't read spool file {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if self.send(body).await.is_err() {
+                return false;
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                eprintln!("Couldn't remove spool file {}: {}", path.display(), e);
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn send(&self, body: Vec<u8>) -> Result<(), anyhow::Error> {
+        let content_type = match self.config.format {
+            ShipperFormat::Lines | ShipperFormat::ElasticBulk { .. } => "application/x-ndjson",
+            ShipperFormat::Loki { .. } => "application/json",
+        };
+        let response = self.client
+            .post(&self.config.endpoint)
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_ENCODING, "gzip")
+            .body(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("log endpoint returned {}", response.status())
+        }
+        Ok(())
+    }
+}
+
+fn encode(format: &ShipperFormat, batch: &[Line]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut body = Vec::new();
+    match format {
+        ShipperFormat::Lines => {
+            for (_, line) in batch {
+                serde_json::to_writer(&mut body, &json!({ "message": line }))?;
+                body.push(b'\n');
+            }
+        }
+        ShipperFormat::ElasticBulk { index } => {
+            for (_, line) in batch {
+                serde_json::to_writer(&mut body, &json!({ "index": { "_index": index } }))?;
+                body.push(b'\n');
+                serde_json::to_writer(&mut body, &json!({ "message": line }))?;
+                body.push(b'\n');
+            }
+        }
+        ShipperFormat::Loki { labels } => {
+            let stream: serde_json::Map<String, serde_json::Value> = labels
+                .iter()
+                .map(|(k, v)| (k.clone(), json!(v)))
+                .collect();
+            let values: Vec<[String; 2]> = batch
+                .iter()
+                .map(|(time, line)| [time.to_string(), line.clone()])
+                .collect();
+            serde_json::to_writer(&mut body, &json!({ "streams": [{ "stream": stream, "values": values }] }))?;
+        }
+    }
+    Ok(body)
+}
+
+fn gzip(body: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    Ok(encoder.finish()?)
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0)
+}
+
+struct Spool {
+    directory: PathBuf,
+    max_files: usize,
+    seq: AtomicU64,
+}
+
+impl Spool {
+    fn new(directory: PathBuf, max_files: usize) -> Result<Self, anyhow::Error> {
+        fs::create_dir_all(&directory)?;
+        Ok(Spool {
+            directory,
+            max_files,
+            seq: AtomicU64::new(0),
+        })
+    }
+
+    /// 先写临时文件再重命名，避免崩溃时留下半个批次
+    fn push(&self, body: &[u8]) -> Result<(), anyhow::Error> {
+        let files = self.list();
+        //新文件占一个名额
+        let remove = (files.len() + 1).saturating_sub(self.max_files);
+        for path in files.iter().take(remove) {
+            fs::remove_file(path)?;
+        }
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{:032}-{:06}.gz", now_nanos(), seq);
+        let tmp = self.directory.join(format!("{}.tmp", name));
+        fs::write(&tmp, body)?;
+        fs::rename(&tmp, self.directory.join(name))?;
+        Ok(())
+    }
+
+    fn list(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.directory)
+            .map(|dir| {
+                dir.filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().map(|ext| ext == "gz").unwrap_or(false))
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use actix_web::web::{Bytes, Data};
+    use actix_web::{post, App, HttpRequest, HttpResponse, HttpServer};
+
+    use crate::log_shipper::{LogShipper, ShipperBuilder, ShipperFormat};
+
+    #[derive(Default)]
+    struct MockState {
+        down: AtomicBool,
+        bodies: Mutex<Vec<String>>,
+    }
+
+    #[post("/ingest")]
+    async fn ingest(state: Data<MockState>, request: HttpRequest, body: Bytes) -> HttpResponse {
+        if state.down.load(Ordering::SeqCst) {
+            return HttpResponse::ServiceUnavailable().finish();
+        }
+        //actix 根据 Content-Encoding 自动解压，能读到明文即说明请求体是合法的 gzip
+        assert_eq!(request.headers().get("content-encoding").unwrap(), "gzip");
+        state.bodies.lock().unwrap().push(String::from_utf8(body.to_vec()).unwrap());
+        HttpResponse::Ok().finish()
+    }
+
+    fn mock_server(state: Data<MockState>) -> Result<String, anyhow::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = HttpServer::new(move || App::new().app_data(state.clone()).service(ingest))
+            .workers(1)
+            .listen(listener)?
+            .run();
+        actix_web::rt::spawn(server);
+        Ok(format!("http://{}/ingest", addr))
+    }
+
+    fn directory(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    async fn wait_until<F: Fn() -> bool>(f: F) -> bool {
+        for _ in 0..100 {
+            if f() {
+                return true;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        false
+    }
+
+    #[actix_web::test]
+    async fn test_batches_are_gzipped() -> Result<(), anyhow::Error> {
+        let state = Data::new(MockState::default());
+        let endpoint = mock_server(state.clone())?;
+        let dir = directory("shipper-batch");
+        let builder = ShipperBuilder::default()
+            .endpoint(endpoint)
+            .format(ShipperFormat::ElasticBulk { index: "app".to_string() })
+            .batch_size(2usize)
+            .clone();
+        let (mut shipper, guard) = LogShipper::from_builder(builder, &dir)?;
+        shipper.write_all(b"one\n")?;
+        shipper.write_all(b"two\n")?;
+        assert!(wait_until(|| state.bodies.lock().unwrap().len() == 1).await);
+        let body = state.bodies.lock().unwrap()[0].clone();
+        assert_eq!(
+            body,
+            "{\"index\":{\"_index\":\"app\"}}\n{\"message\":\"one\"}\n{\"index\":{\"_index\":\"app\"}}\n{\"message\":\"two\"}\n"
+        );
+        drop(shipper);
+        drop(guard);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_spool_and_replay() -> Result<(), anyhow::Error> {
+        let state = Data::new(MockState::default());
+        state.down.store(true, Ordering::SeqCst);
+        let endpoint = mock_server(state.clone())?;
+        let dir = directory("shipper-spool");
+        let builder = ShipperBuilder::default()
+            .endpoint(endpoint)
+            .flush_interval(Duration::from_millis(50))
+            .max_retry(1usize)
+            .retry_delay(Duration::from_millis(10))
+            .clone();
+        let (mut shipper, guard) = LogShipper::from_builder(builder, &dir)?;
+        shipper.write_all(b"while down\n")?;
+        let spool = dir.join("spool");
+        let spooled = || std::fs::read_dir(&spool).map(|d| d.count()).unwrap_or(0);
+        assert!(wait_until(|| spooled() == 1).await);
+        assert!(state.bodies.lock().unwrap().is_empty());
+
+        state.down.store(false, Ordering::SeqCst);
+        assert!(wait_until(|| spooled() == 0).await);
+        assert_eq!(state.bodies.lock().unwrap().clone(), vec!["{\"message\":\"while down\"}\n".to_string()]);
+        drop(shipper);
+        drop(guard);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_replay_before_new_batch() -> Result<(), anyhow::Error> {
+        let state = Data::new(MockState::default());
+        state.down.store(true, Ordering::SeqCst);
+        let endpoint = mock_server(state.clone())?;
+        let dir = directory("shipper-order");
+        //定时重放间隔足够长，积压只能在发送新批次时重放
+        let builder = ShipperBuilder::default()
+            .endpoint(endpoint)
+            .batch_size(1usize)
+            .flush_interval(Duration::from_secs(60))
+            .max_retry(0usize)
+            .clone();
+        let (mut shipper, guard) = LogShipper::from_builder(builder, &dir)?;
+        shipper.write_all(b"first\n")?;
+        let spool = dir.join("spool");
+        let spooled = || std::fs::read_dir(&spool).map(|d| d.count()).unwrap_or(0);
+        assert!(wait_until(|| spooled() == 1).await);
+
+        state.down.store(false, Ordering::SeqCst);
+        shipper.write_all(b"second\n")?;
+        assert!(wait_until(|| state.bodies.lock().unwrap().len() == 2).await);
+        assert_eq!(
+            state.bodies.lock().unwrap().clone(),
+            vec!["{\"message\":\"first\"}\n".to_string(), "{\"message\":\"second\"}\n".to_string()]
+        );
+        assert_eq!(spooled(), 0);
+        drop(shipper);
+        drop(guard);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_guard_does_not_hang_on_full_queue() -> Result<(), anyhow::Error> {
+        let state = Data::new(MockState::default());
+        state.down.store(true, Ordering::SeqCst);
+        let endpoint = mock_server(state.clone())?;
+        let dir = directory("shipper-full");
+        let builder = ShipperBuilder::default()
+            .endpoint(endpoint)
+            .batch_size(1usize)
+            .queue_size(1usize)
+            .max_retry(3usize)
+            .retry_delay(Duration::from_millis(500))
+            .shutdown_timeout(Duration::from_millis(200))
+            .clone();
+        let (mut shipper, guard) = LogShipper::from_builder(builder, &dir)?;
+        //后台线程卡在重试中，队列随即被写满
+        for _ in 0..10 {
+            shipper.write_all(b"line\n")?;
+        }
+        assert!(wait_until(|| shipper.dropped() > 0).await);
+        let start = Instant::now();
+        drop(guard);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        drop(shipper);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spool_limit() -> Result<(), anyhow::Error> {
+        let directory = directory("shipper-spool-limit");
+        let builder = ShipperBuilder::default().endpoint("http://127.0.0.1:9/").max_spool_files(0usize).clone();
+        assert!(LogShipper::from_builder(builder, &directory).is_err());
+        let spool = super::Spool::new(directory.join("spool"), 2)?;
+        for body in ["a", "b", "c"] {
+            spool.push(body.as_bytes())?;
+        }
+        let files = spool.list();
+        assert_eq!(files.len(), 2);
+        assert_eq!(std::fs::read(&files[0])?, b"b");
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_loki_format() -> Result<(), anyhow::Error> {
+        let format = ShipperFormat::Loki { labels: vec![("service".to_string(), "tokio-learn".to_string())] };
+        let body = super::encode(&format, &[(1, "hello".to_string())])?;
+        assert_eq!(
+            String::from_utf8(body)?,
+            "{\"streams\":[{\"stream\":{\"service\":\"tokio-learn\"},\"values\":[[\"1\",\"hello\"]]}]}"
+        );
+        Ok(())
+    }
+}
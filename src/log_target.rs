@@ -0,0 +1,284 @@
+use std::io::Write;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use chrono::SecondsFormat;
+
+/// journald 原生协议默认套接字
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// 本机 syslog 默认套接字
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+    Debug = 7,
+}
+
+impl Severity {
+    /// 从格式化后的日志行中识别级别，找不到时按 INFO 处理
+    pub fn from_line(line: &str) -> Self {
+        line.split_whitespace()
+            .take(6)
+            .find_map(|token| match token {
+                "ERROR" => Some(Severity::Error),
+                "WARN" => Some(Severity::Warning),
+                "INFO" => Some(Severity::Info),
+                "DEBUG" | "TRACE" => Some(Severity::Debug),
+                _ => None,
+            })
+            .unwrap_or(Severity::Info)
+    }
+}
+
+enum Transport {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl Transport {
+    fn send(&self, payload: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Unix(socket) => socket.send(payload),
+            Transport::Udp(socket) => socket.send(payload),
+        }
+    }
+}
+
+/// RFC 5424 syslog 输出，每次 write 作为一条消息发送
+pub struct SyslogWriter {
+    transport: Transport,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+}
+
+impl SyslogWriter {
+    pub fn unix<P: AsRef<Path>>(path: P, app_name: &str) -> Result<Self, anyhow::Error> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self::new(Transport::Unix(socket), app_name))
+    }
+
+    pub fn udp<A: ToSocketAddrs>(addr: A, app_name: &str) -> Result<Self, anyhow::Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        Ok(Self::new(Transport::Udp(socket), app_name))
+    }
+
+    fn new(transport: Transport, app_name: &str) -> Self {
+        SyslogWriter {
+            transport,
+            //local0
+            facility: 16,
+            hostname: hostname(),
+            app_name: app_name.to_string(),
+            pid: std::process::id(),
+        }
+    }
+
+    pub fn facility(mut self, facility: u8) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    fn format(&self, line: &str) -> String {
+        let pri = self.facility as u32 * 8 + Severity::from_line(line) as u32;
+        let timestamp = chrono::Local::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+        format!(
+            "<{}>1 {} {} {} {} - - {}",
+            pri, timestamp, self.hostname, self.app_name, self.pid, line
+        )
+    }
+}
+
+impl Write for SyslogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        let message = self.format(line.trim_end());
+        self.transport.send(message.as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// journald 原生协议输出
+pub struct JournaldWriter {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl JournaldWriter {
+    pub fn new<P: AsRef<Path>>(path: P, identifier: &str) -> Result<Self, anyhow::Error> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(JournaldWriter {
+            socket,
+            identifier: identifier.to_string(),
+        })
+    }
+
+    fn encode(&self, line: &str) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let priority = (Severity::from_line(line) as u8).to_string();
+        append_field(&mut payload, "PRIORITY", &priority);
+        append_field(&mut payload, "SYSLOG_IDENTIFIER", &self.identifier);
+        append_field(&mut payload, "MESSAGE", line);
+        payload
+    }
+}
+
+fn append_field(payload: &mut Vec<u8>, key: &str, value: &str) {
+    payload.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        //多行值使用二进制格式：KEY\n + 小端 u64 长度 + 值 + \n
+        payload.push(b'\n');
+        payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        payload.push(b'=');
+    }
+    payload.extend_from_slice(value.as_bytes());
+    payload.push(b'\n');
+}
+
+impl Write for JournaldWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        self.socket.send(&self.encode(line.trim_end()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 将同一行日志写入多个目标，某个目标失败不影响其他目标
+#[derive(Default)]
+pub struct TeeWriter<'a> {
+    writers: Vec<Box<dyn Write + Send + 'a>>,
+}
+
+impl<'a> TeeWriter<'a> {
+    pub fn with<W: Write + Send + 'a>(mut self, writer: W) -> Self {
+        self.writers.push(Box::new(writer));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writers.is_empty()
+    }
+}
+
+impl<'a> Write for TeeWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut result = Ok(buf.len());
+        for writer in self.writers.iter_mut() {
+            if let Err(e) = writer.write_all(buf) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut result = Ok(());
+        for writer in self.writers.iter_mut() {
+            if let Err(e) = writer.flush() {
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::net::UdpSocket;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+
+    use crate::log_target::{JournaldWriter, Severity, SyslogWriter, TeeWriter};
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_severity_from_line() {
+        assert_eq!(Severity::from_line("2024-12-12 12:00:00.000 ERROR main store: boom"), Severity::Error);
+        assert_eq!(Severity::from_line("2024-12-12 12:00:00.000  WARN main store: slow"), Severity::Warning);
+        assert_eq!(Severity::from_line("no level here"), Severity::Info);
+    }
+
+    #[test]
+    fn test_syslog_unix() -> Result<(), anyhow::Error> {
+        let path = socket_path("syslog");
+        let listener = UnixDatagram::bind(&path)?;
+        let mut writer = SyslogWriter::unix(&path, "tokio-learn")?;
+        writer.write_all(b"2024-12-12 12:00:00.000  WARN main store: slow\n")?;
+        let mut buf = [0u8; 1024];
+        let size = listener.recv(&mut buf)?;
+        let message = String::from_utf8_lossy(&buf[..size]).to_string();
+        //local0(16) * 8 + warning(4)
+        assert!(message.starts_with("<132>1 "));
+        assert!(message.contains(&format!(" tokio-learn {} - - ", std::process::id())));
+        assert!(message.ends_with("store: slow"));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_syslog_udp() -> Result<(), anyhow::Error> {
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        let mut writer = SyslogWriter::udp(listener.local_addr()?, "tokio-learn")?.facility(1);
+        writer.write_all(b"INFO hello\n")?;
+        let mut buf = [0u8; 1024];
+        let size = listener.recv(&mut buf)?;
+        let message = String::from_utf8_lossy(&buf[..size]).to_string();
+        assert!(message.starts_with("<14>1 "));
+        assert!(message.ends_with("INFO hello"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_journald_and_tee() -> Result<(), anyhow::Error> {
+        let path = socket_path("journald");
+        let listener = UnixDatagram::bind(&path)?;
+        let journald = JournaldWriter::new(&path, "tokio-learn")?;
+        let mut copy = Vec::new();
+        {
+            let mut tee = TeeWriter::default().with(journald).with(&mut copy);
+            tee.write_all(b"ERROR first\nsecond\n")?;
+        }
+        assert_eq!(copy, b"ERROR first\nsecond\n");
+
+        let mut buf = [0u8; 1024];
+        let size = listener.recv(&mut buf)?;
+        let mut expected = b"PRIORITY=3\nSYSLOG_IDENTIFIER=tokio-learn\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&(18u64).to_le_bytes());
+        expected.extend_from_slice(b"ERROR first\nsecond\n");
+        assert_eq!(&buf[..size], expected.as_slice());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use actix_web::cookie::time::UtcOffset;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::time::OffsetTime;
+use tracing_subscriber::fmt::format::{DefaultFields, Format};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Layer, Registry};
+
+use crate::file_appender::{AppenderBuilder, MaxFiles, Rotation, TracingFileAppender};
+use crate::log_buffer::{self, LogBuffer};
+use crate::log_crypto::EncryptionKey;
+use crate::log_shipper::{LogShipper, ShipperBuilder, ShipperGuard};
+use crate::log_target::{JournaldWriter, SyslogWriter, TeeWriter};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// 多行、带颜色的开发格式
+    Pretty,
+    /// 单行文本
+    #[default]
+    Plain,
+    Json,
+}
+
+/// 日志输出配置，从 `LOG_` 前缀的环境变量读取，例如 `LOG_STDOUT_LEVEL=debug`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub stdout: bool,
+    pub stdout_level: String,
+    pub stdout_format: LogFormat,
+    pub stdout_ansi: bool,
+
+    pub file: bool,
+    pub file_level: String,
+    pub file_format: LogFormat,
+    pub directory: String,
+    pub rotation: Rotation,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+    /// 保留的日志文件数，0 表示不清理
+    pub max_files: usize,
+    /// 64 位十六进制密钥，设置后日志文件加密写入
+    pub encryption_key: Option<String>,
+
+    /// syslog 与 journald 从单行文本中识别级别，固定使用 plain 格式
+    pub syslog_unix: Option<String>,
+    pub syslog_udp: Option<String>,
+    pub syslog_level: String,
+    pub journald: Option<String>,
+    pub journald_level: String,
+    pub shipper_url: Option<String>,
+    pub shipper_level: String,
+    pub shipper_format: LogFormat,
+
+    pub buffer_capacity: usize,
+    pub buffer_level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            stdout: true,
+            stdout_level: "info".to_string(),
+            stdout_format: LogFormat::Pretty,
+            stdout_ansi: true,
+            file: true,
+            file_level: "info".to_string(),
+            file_format: LogFormat::Plain,
+            directory: "logs".to_string(),
+            rotation: Rotation::Daily,
+            prefix: None,
+            suffix: Some("log".to_string()),
+            max_files: 0,
+            encryption_key: None,
+            syslog_unix: None,
+            syslog_udp: None,
+            syslog_level: "info".to_string(),
+            journald: None,
+            journald_level: "info".to_string(),
+            shipper_url: None,
+            shipper_level: "info".to_string(),
+            shipper_format: LogFormat::Plain,
+            buffer_capacity: log_buffer::DEFAULT_CAPACITY,
+            buffer_level: "info".to_string(),
+        }
+    }
+}
+
+/// 持有各后台写线程的 guard，drop 时刷新剩余日志
+pub struct LoggingGuard {
+    _writers: Vec<WorkerGuard>,
+    _shipper: Option<ShipperGuard>,
+    /// 文件等输出被写线程释放（已刷新并落盘）时收到通知
+    closed: Vec<mpsc::Receiver<()>>,
+    pub reload: LogReload,
+}
+
+impl LoggingGuard {
+    /// 停止写线程并等待文件刷新落盘，超时返回 false
+    pub fn close(mut self, timeout: Duration) -> bool {
+        self._writers.clear();
+        let deadline = Instant::now() + timeout;
+        let closed = self.closed
+            .drain(..)
+            .all(|closed| closed.recv_timeout(deadline.saturating_duration_since(Instant::now())).is_ok());
+        //shipper 的写端已随写线程释放，再等待剩余批次发送或落入 spool
+        self._shipper.take();
+        closed
+    }
+
+    /// 为输出启动独立的后台写线程，返回带级别过滤的 layer
+    fn spawn_writer<W>(&mut self, name: &str, level: &str, format: LogFormat, writer: W) -> Result<(BoxedLayer, LevelHandle), anyhow::Error>
+        where W: Write + Send + 'static
+    {
+        let (level, handle) = reload::Layer::new(parse_level(name, level)?);
+        let (closed, receiver) = mpsc::channel();
+        let writer = NotifyOnDrop {
+            inner: Some(writer),
+            closed,
+        };
+        let (non_blocking, guard) = tracing_appender::non_blocking(writer);
+        self._writers.push(guard);
+        self.closed.push(receiver);
+        Ok((fmt_layer(format, false, non_blocking).with_filter(level).boxed(), handle))
+    }
+}
+
+/// 被释放时先释放内部 writer 再发出通知
+struct NotifyOnDrop<W: Write> {
+    inner: Option<W>,
+    closed: mpsc::Sender<()>,
+}
+
+impl<W: Write> Write for NotifyOnDrop<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.as_mut().map_or(Ok(buf.len()), |inner| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.as_mut().map_or(Ok(()), |inner| inner.flush())
+    }
+}
+
+impl<W: Write> Drop for NotifyOnDrop<W> {
+    fn drop(&mut self) {
+        drop(self.inner.take());
+        let _ = self.closed.send(());
+    }
+}
+
+pub(crate) type LevelHandle = reload::Handle<LevelFilter, Registry>;
+
+/// 运行时可调整的日志设置
+#[derive(Default)]
+pub struct LogReload {
+    pub(crate) stdout: Option<LevelHandle>,
+    pub(crate) file: Option<LevelHandle>,
+    pub(crate) syslog: Option<LevelHandle>,
+    pub(crate) journald: Option<LevelHandle>,
+    pub(crate) shipper: Option<LevelHandle>,
+    pub(crate) buffer: Option<LevelHandle>,
+    pub(crate) max_files: MaxFiles,
+}
+
+impl LogReload {
+    /// 重新设置各输出的级别与文件保留数，任一级别无效时不做任何修改
+    pub fn apply(&self, config: &LogConfig) -> Result<(), anyhow::Error> {
+        let levels = [
+            (&self.stdout, "LOG_STDOUT_LEVEL", &config.stdout_level),
+            (&self.file, "LOG_FILE_LEVEL", &config.file_level),
+            (&self.syslog, "LOG_SYSLOG_LEVEL", &config.syslog_level),
+            (&self.journald, "LOG_JOURNALD_LEVEL", &config.journald_level),
+            (&self.shipper, "LOG_SHIPPER_LEVEL", &config.shipper_level),
+            (&self.buffer, "LOG_BUFFER_LEVEL", &config.buffer_level),
+        ];
+        let levels = levels
+            .into_iter()
+            .map(|(handle, name, value)| Ok((handle, name, parse_level(name, value)?)))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        for (handle, name, level) in levels {
+            if let Some(handle) = handle {
+                handle.reload(level).map_err(|e| anyhow!("cannot reload {}: {}", name, e))?;
+            }
+        }
+        self.max_files.set(config.max_files);
+        Ok(())
+    }
+}
+
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+pub fn parse_level(name: &str, value: &str) -> Result<LevelFilter, anyhow::Error> {
+    LevelFilter::from_str(value).map_err(|_| anyhow!("invalid {}: {}", name, value))
+}
+
+fn local_time() -> OffsetTime<Vec<actix_web::cookie::time::format_description::FormatItem<'static>>> {
+    let time_offset =
+        UtcOffset::current_local_offset().unwrap_or_else(|_| UtcOffset::from_hms(8, 0, 0).unwrap());
+    OffsetTime::new(
+        time_offset,
+        actix_web::cookie::time::macros::format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]"
+        )
+        .to_vec(),
+    )
+}
+
+fn event_format() -> Format {
+    fmt::format()
+        .with_level(true)
+        .with_line_number(true)
+        .with_thread_names(true)
+        .with_target(true)
+}
+
+fn fmt_layer<W>(format: LogFormat, ansi: bool, writer: W) -> BoxedLayer
+    where W: for<'w> fmt::MakeWriter<'w> + Send + Sync + 'static
+{
+    let layer = fmt::layer::<Registry>()
+        .fmt_fields(DefaultFields::new())
+        .with_ansi(ansi)
+        .with_writer(writer);
+    match format {
+        LogFormat::Pretty => layer
+            .event_format(event_format().with_timer(local_time()).pretty())
+            .boxed(),
+        LogFormat::Plain => layer
+            .event_format(event_format().with_timer(local_time()))
+            .boxed(),
+        LogFormat::Json => layer
+            .json()
+            .with_timer(local_time())
+            .with_line_number(true)
+            .with_thread_names(true)
+            .boxed(),
+    }
+}
+
+/// 按配置组装 stdout、文件、syslog、journald、shipper 与内存缓冲等输出，各自独立过滤级别
+///
+/// `extra` 为其他需要挂到同一订阅者上的 layer，例如 OpenTelemetry 导出
+pub fn init(config: &'static LogConfig, extra: Vec<BoxedLayer>) -> Result<(LogBuffer, LoggingGuard), anyhow::Error> {
+    let mut layers: Vec<BoxedLayer> = extra;
+    let max_files = MaxFiles::new(config.max_files);
+    let mut guard = LoggingGuard {
+        _writers: vec![],
+        _shipper: None,
+        closed: vec![],
+        reload: LogReload {
+            stdout: None,
+            file: None,
+            syslog: None,
+            journald: None,
+            shipper: None,
+            buffer: None,
+            max_files: max_files.clone(),
+        },
+    };
+
+    if config.stdout {
+        let (level, handle) = reload::Layer::new(parse_level("LOG_STDOUT_LEVEL", &config.stdout_level)?);
+        layers.push(fmt_layer(config.stdout_format, config.stdout_ansi, std::io::stdout).with_filter(level).boxed());
+        guard.reload.stdout = Some(handle);
+    }
+
+    if config.file {
+        let encryption = config.encryption_key
+            .as_deref()
+            .map(EncryptionKey::from_hex)
+            .transpose()?;
+        let builder = AppenderBuilder::default()
+            .rotation(config.rotation.clone())
+            .prefix(config.prefix.as_deref())
+            .suffix(config.suffix.as_deref())
+            .encryption(encryption)
+            .max_files(max_files)
+            .clone();
+        let appender = TracingFileAppender::from_builder(builder, config.directory.as_str())?;
+        let (layer, handle) = guard.spawn_writer("LOG_FILE_LEVEL", &config.file_level, config.file_format, appender)?;
+        layers.push(layer);
+        guard.reload.file = Some(handle);
+    }
+
+    let mut syslog = TeeWriter::default();
+    if let Some(path) = &config.syslog_unix {
+        syslog = syslog.with(SyslogWriter::unix(path, env!("CARGO_PKG_NAME"))?);
+    }
+    if let Some(addr) = &config.syslog_udp {
+        syslog = syslog.with(SyslogWriter::udp(addr, env!("CARGO_PKG_NAME"))?);
+    }
+    if !syslog.is_empty() {
+        let (layer, handle) = guard.spawn_writer("LOG_SYSLOG_LEVEL", &config.syslog_level, LogFormat::Plain, syslog)?;
+        layers.push(layer);
+        guard.reload.syslog = Some(handle);
+    }
+    if let Some(path) = &config.journald {
+        let journald = JournaldWriter::new(path, env!("CARGO_PKG_NAME"))?;
+        let (layer, handle) = guard.spawn_writer("LOG_JOURNALD_LEVEL", &config.journald_level, LogFormat::Plain, journald)?;
+        layers.push(layer);
+        guard.reload.journald = Some(handle);
+    }
+    if let Some(endpoint) = &config.shipper_url {
+        let builder = ShipperBuilder::default().endpoint(endpoint.clone()).clone();
+        let (shipper, shipper_guard) = LogShipper::from_builder(builder, &config.directory)?;
+        let (layer, handle) = guard.spawn_writer("LOG_SHIPPER_LEVEL", &config.shipper_level, config.shipper_format, shipper)?;
+        layers.push(layer);
+        guard._shipper = Some(shipper_guard);
+        guard.reload.shipper = Some(handle);
+    }
+
+    let log_buffer = LogBuffer::new(config.buffer_capacity);
+    let (level, handle) = reload::Layer::new(parse_level("LOG_BUFFER_LEVEL", &config.buffer_level)?);
+    layers.push(log_buffer.layer().with_filter(level).boxed());
+    guard.reload.buffer = Some(handle);
+
+    tracing_subscriber::registry().with(layers).init();
+    Ok((log_buffer, guard))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Config;
+    use crate::file_appender::Rotation;
+    use crate::logging::{parse_level, LogConfig, LogFormat};
+
+    #[test]
+    fn test_config_from_env_vars() -> Result<(), anyhow::Error> {
+        let vars = vec![
+            ("STDOUT_FORMAT".to_string(), "json".to_string()),
+            ("FILE_LEVEL".to_string(), "warn".to_string()),
+            ("ROTATION".to_string(), "hourly".to_string()),
+            ("STDOUT".to_string(), "false".to_string()),
+        ];
+        let config: LogConfig = envy::from_iter(vars)?;
+        assert_eq!(config.stdout_format, LogFormat::Json);
+        assert!(!config.stdout);
+        assert_eq!(config.file_level, "warn");
+        assert!(matches!(config.rotation, Rotation::Hourly));
+        assert_eq!(config.file_format, LogFormat::Plain);
+        Ok(())
+    }
+
+    #[test]
+    fn test_destination_settings() -> Result<(), anyhow::Error> {
+        let vars = vec![
+            ("SYSLOG_LEVEL".to_string(), "warn".to_string()),
+            ("SHIPPER_LEVEL".to_string(), "error".to_string()),
+            ("SHIPPER_FORMAT".to_string(), "json".to_string()),
+        ];
+        let config: LogConfig = envy::from_iter(vars)?;
+        assert_eq!(config.syslog_level, "warn");
+        assert_eq!(config.journald_level, "info");
+        assert_eq!(config.shipper_level, "error");
+        assert_eq!(config.shipper_format, LogFormat::Json);
+        assert_eq!(config.file_format, LogFormat::Plain);
+        let vars = vec![("LOG_JOURNALD_LEVEL".to_string(), "loud".to_string())];
+        let error = Config::from_sources(None, None, vars).unwrap_err();
+        assert_eq!(error.to_string(), "invalid LOG_JOURNALD_LEVEL: loud");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_level() {
+        assert!(parse_level("LOG_FILE_LEVEL", "debug").is_ok());
+        assert!(parse_level("LOG_FILE_LEVEL", "verbose").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use migration::sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::tasks;
+
+/// 进程内的 Prometheus 指标，由 `/metrics` 以文本格式导出
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub db_pool_size: IntGauge,
+    pub db_pool_idle: IntGauge,
+    /// 从连接池取得连接并开启事务的耗时
+    pub db_pool_wait: HistogramVec,
+    pub optimistic_lock_conflicts: IntCounterVec,
+    pub ref_count_zero_reached: IntCounterVec,
+    pub rate_limited: IntCounterVec,
+    pub retry_attempts: IntCounter,
+    pub appender_bytes_written: IntCounter,
+    pub appender_rollovers: IntCounter,
+    pub appender_errors: IntCounter,
+    pub tasks_in_flight: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let metrics = Metrics {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled"),
+                &["route", "method", "status"],
+            )?,
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+                &["route", "method", "status"],
+            )?,
+            db_pool_size: IntGauge::new("db_pool_connections", "Connections currently open in the pool")?,
+            db_pool_idle: IntGauge::new("db_pool_idle_connections", "Idle connections in the pool")?,
+            db_pool_wait: HistogramVec::new(
+                HistogramOpts::new("db_pool_wait_seconds", "Time spent acquiring a pooled connection")
+                    .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]),
+                &["operation"],
+            )?,
+            optimistic_lock_conflicts: IntCounterVec::new(
+                Opts::new("optimistic_lock_conflicts_total", "Updates rejected by the version check"),
+                &["operation"],
+            )?,
+            ref_count_zero_reached: IntCounterVec::new(
+                Opts::new("ref_count_zero_reached_total", "Reference counts that reached zero"),
+                &["action"],
+            )?,
+            rate_limited: IntCounterVec::new(
+                Opts::new("rate_limited_requests_total", "Requests rejected by the rate limiter"),
+                &["route"],
+            )?,
+            retry_attempts: IntCounter::new("retry_attempts_total", "Retries performed by async_retry")?,
+            appender_bytes_written: IntCounter::new("log_appender_bytes_written_total", "Bytes written to log files")?,
+            appender_rollovers: IntCounter::new("log_appender_rollovers_total", "Log file rollovers")?,
+            appender_errors: IntCounter::new("log_appender_errors_total", "Log file write, flush and rollover errors")?,
+            tasks_in_flight: IntGaugeVec::new(
+                Opts::new("tasks_in_flight", "Background tasks still running"),
+                &["name"],
+            )?,
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.http_requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.http_request_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.db_pool_size.clone()))?;
+        metrics.registry.register(Box::new(metrics.db_pool_idle.clone()))?;
+        metrics.registry.register(Box::new(metrics.db_pool_wait.clone()))?;
+        metrics.registry.register(Box::new(metrics.optimistic_lock_conflicts.clone()))?;
+        metrics.registry.register(Box::new(metrics.ref_count_zero_reached.clone()))?;
+        metrics.registry.register(Box::new(metrics.rate_limited.clone()))?;
+        metrics.registry.register(Box::new(metrics.retry_attempts.clone()))?;
+        metrics.registry.register(Box::new(metrics.appender_bytes_written.clone()))?;
+        metrics.registry.register(Box::new(metrics.appender_rollovers.clone()))?;
+        metrics.registry.register(Box::new(metrics.appender_errors.clone()))?;
+        metrics.registry.register(Box::new(metrics.tasks_in_flight.clone()))?;
+        Ok(metrics)
+    }
+
+    pub fn observe_request(&self, route: &str, method: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [route, method, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration.with_label_values(&labels).observe(elapsed.as_secs_f64());
+    }
+
+    /// 刷新采样型指标（连接池、在途任务）后编码为 Prometheus 文本格式
+    pub fn render(&self, conn: Option<&DatabaseConnection>) -> Result<String, anyhow::Error> {
+        if let Some(conn) = conn.filter(|conn| conn.get_database_backend() == DatabaseBackend::Postgres) {
+            let pool = conn.get_postgres_connection_pool();
+            self.db_pool_size.set(pool.size() as i64);
+            self.db_pool_idle.set(pool.num_idle() as i64);
+        }
+        self.tasks_in_flight.reset();
+        for (name, count) in tasks::registry().in_flight_by_name() {
+            self.tasks_in_flight.with_label_values(&[name]).set(count as i64);
+        }
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| anyhow!("metrics are not utf-8: {}", e))
+    }
+}
+
+/// 进程级指标
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("metric definitions are valid"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::metrics::metrics;
+
+    #[test]
+    fn test_render_text_format() -> Result<(), anyhow::Error> {
+        metrics().observe_request("/metrics-test", "GET", 200, Duration::from_millis(5));
+        metrics().optimistic_lock_conflicts.with_label_values(&["metrics-test"]).inc();
+        let text = metrics().render(None)?;
+        assert!(text.contains(r#"http_requests_total{method="GET",route="/metrics-test",status="200"} "#));
+        assert!(text.contains(r#"http_request_duration_seconds_bucket{method="GET",route="/metrics-test",status="200",le="0.005"}"#));
+        assert!(text.contains(r#"optimistic_lock_conflicts_total{operation="metrics-test"} "#));
+        assert!(text.contains("# TYPE log_appender_bytes_written_total counter"));
+        Ok(())
+    }
+}
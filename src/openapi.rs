@@ -0,0 +1,415 @@
+use std::collections::BTreeMap;
+
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use actix_web::{get, HttpResponse};
+use serde_json::{json, Map, Value};
+
+use crate::auth::Role;
+use crate::error::ErrorBody;
+use crate::{api_key, country, health, router};
+
+/// 提交到仓库的规范，由 `tokio-learn openapi > openapi.json` 生成
+pub const SPEC_FILE: &str = "openapi.json";
+
+/// DTO 的 JSON Schema，与 serde 定义写在同一模块，由测试校验两者一致
+pub trait Schema {
+    const NAME: &'static str;
+
+    fn schema() -> Value;
+
+    /// 合法的示例，请求体的示例需能被反序列化
+    fn example() -> Value;
+}
+
+pub fn integer() -> Value {
+    json!({"type": "integer", "format": "int64"})
+}
+
+pub fn string() -> Value {
+    json!({"type": "string"})
+}
+
+pub fn date_time() -> Value {
+    json!({"type": "string", "format": "date-time", "description": "UTC，不带时区后缀"})
+}
+
+pub fn nullable(mut schema: Value) -> Value {
+    schema["nullable"] = json!(true);
+    schema
+}
+
+pub fn described(mut schema: Value, description: &str) -> Value {
+    schema["description"] = json!(description);
+    schema
+}
+
+/// `properties` 按声明顺序输出，`required` 为必填字段
+pub fn object(properties: &[(&str, Value)], required: &[&str]) -> Value {
+    let properties: Map<String, Value> = properties
+        .iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    json!({"type": "object", "properties": properties, "required": required, "additionalProperties": false})
+}
+
+/// 接口使用的请求头参数
+pub fn header(name: &str, required: bool, description: &str) -> Value {
+    json!({"name": name, "in": "header", "required": required, "description": description, "schema": string()})
+}
+
+pub fn path_id(description: &str) -> Value {
+    json!({"name": "id", "in": "path", "required": true, "description": description, "schema": integer()})
+}
+
+/// OpenAPI 3 文档，各模块在 `openapi(doc)` 中登记自己的路由
+pub struct Document {
+    paths: BTreeMap<String, Map<String, Value>>,
+    schemas: BTreeMap<&'static str, Value>,
+}
+
+/// 单个接口
+pub struct Operation {
+    value: Value,
+}
+
+impl Operation {
+    pub fn new(id: &str, summary: &str, tag: &str) -> Self {
+        Operation {
+            value: json!({"operationId": id, "summary": summary, "tags": [tag], "parameters": [], "responses": {}}),
+        }
+    }
+
+    /// 需要的最低角色，同时声明两种认证方式
+    pub fn role(mut self, role: Role) -> Self {
+        self.value["security"] = json!([{"apiKey": []}, {"bearer": []}]);
+        self.value["x-required-role"] = json!(role.to_string());
+        self.error("401", "unauthorized").error("403", "forbidden")
+    }
+
+    pub fn parameter(mut self, parameter: Value) -> Self {
+        self.value["parameters"].as_array_mut().expect("parameters").push(parameter);
+        self
+    }
+
+    /// 把查询参数对象的每个属性展开为 `in: query` 参数
+    pub fn query<T: Schema>(mut self) -> Self {
+        if let Some(properties) = T::schema()["properties"].as_object() {
+            for (name, property) in properties {
+                self = self.parameter(json!({"name": name, "in": "query", "required": false, "schema": property}));
+            }
+        }
+        self
+    }
+
+    pub fn body(mut self, reference: Value) -> Self {
+        self.value["requestBody"] = json!({"required": true, "content": {"application/json": {"schema": reference}}});
+        self
+    }
+
+    pub fn response(mut self, status: &str, description: &str, reference: Option<Value>) -> Self {
+        let mut response = json!({"description": description});
+        if let Some(reference) = reference {
+            response["content"] = json!({"application/json": {"schema": reference}});
+        }
+        self.value["responses"][status] = response;
+        self
+    }
+
+    /// 非 JSON 响应，例如文本指标与 SSE
+    pub fn content(mut self, status: &str, description: &str, content_type: &str) -> Self {
+        self.value["responses"][status] = json!({
+            "description": description,
+            "content": {content_type: {"schema": string()}},
+        });
+        self
+    }
+
+    pub fn response_header(mut self, status: &str, name: &str, description: &str) -> Self {
+        self.value["responses"][status]["headers"][name] = json!({"description": description, "schema": string()});
+        self
+    }
+
+    /// 支持 `Idempotency-Key`，需在其他响应之后调用，409 的说明会合并
+    pub fn idempotent(mut self) -> Self {
+        self = self.parameter(header(
+            "Idempotency-Key",
+            false,
+            "重试时使用相同的 key，返回首次的响应而不重复修改",
+        ));
+        let in_progress = "相同 Idempotency-Key 的请求仍在处理";
+        let conflict = match self.value["responses"]["409"]["description"].as_str() {
+            Some(description) => format!("{}；{}", description, in_progress),
+            None => in_progress.to_string(),
+        };
+        for (status, _) in self.value["responses"].clone().as_object().expect("responses") {
+            if status.starts_with('2') {
+                self = self.response_header(status, "Idempotent-Replayed", "重放的响应为 true");
+            }
+        }
+        self.error("409", &conflict)
+            .error("422", "Idempotency-Key 已用于不同的请求")
+    }
+
+    /// 错误响应统一使用 [`ErrorBody`]
+    pub fn error(mut self, status: &str, description: &str) -> Self {
+        self.value["responses"][status] = json!({
+            "description": description,
+            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}},
+        });
+        self
+    }
+}
+
+impl Document {
+    pub fn new() -> Self {
+        let mut doc = Document {
+            paths: BTreeMap::new(),
+            schemas: BTreeMap::new(),
+        };
+        doc.schema::<ErrorBody>();
+        doc
+    }
+
+    /// 登记 schema 并返回引用
+    pub fn schema<T: Schema>(&mut self) -> Value {
+        self.schemas.insert(T::NAME, T::schema());
+        json!({"$ref": format!("#/components/schemas/{}", T::NAME)})
+    }
+
+    pub fn route(&mut self, method: &str, path: &str, operation: Operation) {
+        let mut value = operation.value;
+        if value["parameters"].as_array().is_some_and(Vec::is_empty) {
+            value.as_object_mut().expect("operation").remove("parameters");
+        }
+        self.paths.entry(path.to_string()).or_default().insert(method.to_string(), value);
+    }
+
+    /// 已登记的 (method, path)
+    pub fn operations(&self) -> Vec<(String, String)> {
+        self.paths
+            .iter()
+            .flat_map(|(path, methods)| methods.keys().map(move |method| (method.clone(), path.clone())))
+            .collect()
+    }
+
+    pub fn to_value(&self) -> Value {
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": self.schemas,
+                "securitySchemes": {
+                    "apiKey": {"type": "apiKey", "in": "header", "name": "x-api-key"},
+                    "bearer": {"type": "http", "scheme": "bearer", "bearerFormat": "JWT"},
+                },
+            },
+        })
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 完整的接口文档
+pub fn document() -> Document {
+    let mut doc = Document::new();
+    router::openapi(&mut doc);
+    health::openapi(&mut doc);
+    country::openapi(&mut doc);
+    api_key::openapi(&mut doc);
+    doc
+}
+
+/// 带结尾换行的格式化 JSON，与提交的文件逐字节一致
+pub fn render() -> String {
+    let mut text = serde_json::to_string_pretty(&document().to_value()).expect("openapi document");
+    text.push('\n');
+    text
+}
+
+#[get("/openapi.json")]
+pub async fn spec() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "application/json"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .body(render())
+}
+
+/// Swagger UI，静态资源从 CDN 加载
+#[get("/docs")]
+pub async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/html; charset=utf-8"))
+        .body(DOCS_HTML)
+}
+
+const DOCS_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>tokio-learn API</title>
+  <link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://cdn.jsdelivr.net/npm/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use actix_web::http::Method;
+    use actix_web::middleware::from_fn;
+    use actix_web::App;
+    use chrono::NaiveDateTime;
+    use entity::CountryModel;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::Value;
+    use tracing::Level;
+
+    use crate::api_key::{ApiKeyView, CreateApiKey, CreatedApiKey};
+    use crate::auth::{authenticate, Role};
+    use crate::country::{ChangeRefCount, CreateCountry, ListCountries, Page, UpdateCountry};
+    use crate::error::ErrorBody;
+    use crate::health::{Check, Report, Status};
+    use crate::log_buffer::{LogFilter, LogRecord};
+    use crate::openapi::{document, render, Schema, SPEC_FILE};
+    use crate::router;
+    use crate::store::{RefCountOutcome, ZeroAction};
+
+    fn keys(value: &Value) -> BTreeSet<String> {
+        value.as_object().map(|object| object.keys().cloned().collect()).unwrap_or_default()
+    }
+
+    fn properties<T: Schema>() -> BTreeSet<String> {
+        keys(&T::schema()["properties"])
+    }
+
+    /// 请求体示例包含全部属性，且能被 DTO 反序列化
+    fn assert_request<T: Schema + DeserializeOwned>() {
+        assert_eq!(keys(&T::example()), properties::<T>(), "{} example", T::NAME);
+        if let Err(e) = serde_json::from_value::<T>(T::example()) {
+            panic!("{} example rejected: {}", T::NAME, e)
+        }
+    }
+
+    /// 序列化结果的字段与 schema 一致，示例同样
+    fn assert_response<T: Schema + Serialize>(value: &T) {
+        let serialized = serde_json::to_value(value).expect("serialize");
+        assert_eq!(keys(&serialized), properties::<T>(), "{} fields", T::NAME);
+        assert_eq!(keys(&T::example()), properties::<T>(), "{} example", T::NAME);
+        for required in T::schema()["required"].as_array().expect("required") {
+            assert!(serialized.get(required.as_str().expect("name")).is_some(), "{} {}", T::NAME, required);
+        }
+    }
+
+    #[test]
+    fn test_committed_spec() {
+        let committed = include_str!("../openapi.json");
+        assert!(
+            committed == render(),
+            "{} is out of date, run `cargo run -- openapi > {}`",
+            SPEC_FILE,
+            SPEC_FILE
+        );
+    }
+
+    #[test]
+    fn test_schemas_match_dtos() -> Result<(), anyhow::Error> {
+        assert_request::<CreateCountry>();
+        assert_request::<UpdateCountry>();
+        assert_request::<ChangeRefCount>();
+        assert_request::<CreateApiKey>();
+        serde_json::from_value::<ListCountries>(ListCountries::example())?;
+        serde_json::from_value::<LogFilter>(LogFilter::example())?;
+
+        let created_at: NaiveDateTime = "2024-05-01T08:00:00".parse()?;
+        let country = CountryModel {
+            id: 1,
+            name: "中国".to_string(),
+            ref_count: 0,
+            v: 0,
+            created_at,
+        };
+        assert_response(&country);
+        assert_response(&Page {
+            items: vec![country.clone()],
+            total: None,
+            next_cursor: None,
+            next: None,
+        });
+        assert_response(&RefCountOutcome {
+            country,
+            previous: 1,
+            zero_reached: Some(ZeroAction::Delete),
+        });
+        let api_key = ApiKeyView {
+            id: 1,
+            name: "ci".to_string(),
+            prefix: "tl_2cd1ab48".to_string(),
+            roles: vec![Role::Reader],
+            tenant: None,
+            created_at,
+            revoked_at: None,
+        };
+        assert_response(&api_key);
+        assert_response(&CreatedApiKey {
+            api_key,
+            key: "tl_secret".to_string(),
+        });
+        assert_response(&ErrorBody {
+            code: "not_found",
+            message: "country 7 not found".to_string(),
+            request_id: None,
+        });
+        let check = Check {
+            status: Status::Ok,
+            latency_ms: 1.2,
+            error: None,
+        };
+        assert_response(&Report {
+            status: Status::Ok,
+            checks: BTreeMap::from([("database", check)]),
+        });
+        assert_response(&LogRecord {
+            time: "2024-05-01T08:00:00.000000Z".to_string(),
+            level: Level::INFO,
+            target: "tokio_learn".to_string(),
+            message: "hello".to_string(),
+            line: "hello".to_string(),
+        });
+        Ok(())
+    }
+
+    /// 文档中的每个接口都已注册，受保护的接口未认证时返回 401
+    #[actix_web::test]
+    async fn test_documented_routes_exist() {
+        let app = actix_web::test::init_service(App::new().wrap(from_fn(authenticate)).configure(router::routes)).await;
+        let doc = document().to_value();
+        for (method, path) in document().operations() {
+            let request = actix_web::test::TestRequest::default()
+                .method(Method::from_bytes(method.to_uppercase().as_bytes()).expect("method"))
+                .uri(&path.replace("{id}", "1"))
+                .to_request();
+            let status = actix_web::test::call_service(&app, request).await.status().as_u16();
+            assert!(status != 404 && status != 405, "{} {} is not routed: {}", method, path, status);
+            if doc["paths"][&path][&method].get("x-required-role").is_some() {
+                assert_eq!(status, 401, "{} {} is not guarded", method, path);
+            }
+        }
+    }
+}
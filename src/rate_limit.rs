@@ -0,0 +1,657 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+use actix_web::ResponseError;
+use anyhow::{anyhow, bail};
+use migration::sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection, DbErr, Statement};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{warn, Instrument};
+
+use crate::error::AppError;
+use crate::metrics::metrics;
+use crate::store::table_span;
+
+/// 未单独配置时不限流的路由：探针与指标
+const EXEMPT: [&str; 4] = ["/healthz", "/readyz", "/startupz", "/metrics"];
+
+/// 每处理多少次请求清理一次已补满的令牌桶
+const PRUNE_EVERY: u64 = 1024;
+
+const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// 补充令牌后取一个，令牌不足时不扣减；`allowed` 记录本次是否放行
+///
+/// 更新语句中引用的都是旧值，所以补充后的令牌数需要重复计算
+const TAKE_SQL: &str = r#"insert into "rate_limit_bucket" as b ("key", "tokens", "allowed", "updated_at", "full_at")
+values ($1, $2 - 1, true, now(), now() + make_interval(secs => 1 / $3))
+on conflict ("key") do update set
+    "allowed" = least($2, b."tokens" + extract(epoch from now() - b."updated_at") * $3) >= 1,
+    "tokens" = least($2, b."tokens" + extract(epoch from now() - b."updated_at") * $3)
+        - case when least($2, b."tokens" + extract(epoch from now() - b."updated_at") * $3) >= 1 then 1 else 0 end,
+    "updated_at" = now(),
+    "full_at" = now() + make_interval(secs => $2 / $3)
+returning "tokens", "allowed""#;
+
+/// 补充后的令牌数，不扣减；没有记录时桶是满的
+const PEEK_SQL: &str = r#"select least($2, "tokens" + extract(epoch from now() - "updated_at") * $3)::float8 as "tokens"
+from "rate_limit_bucket" where "key" = $1"#;
+
+const PRUNE_SQL: &str = r#"delete from "rate_limit_bucket" where "full_at" < now()"#;
+
+/// 区分调用方的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyKind {
+    /// 客户端 IP
+    #[default]
+    Ip,
+    /// `api_key_header` 请求头，缺少时按 IP
+    ApiKey,
+    /// 路由模板中名为 `token_param` 的路径参数，缺少时按 IP
+    Token,
+}
+
+impl FromStr for KeyKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ip" => Ok(KeyKind::Ip),
+            "api_key" => Ok(KeyKind::ApiKey),
+            "token" => Ok(KeyKind::Token),
+            _ => bail!("rate limit key must be ip, api_key or token, got {}", s),
+        }
+    }
+}
+
+/// 令牌桶存放的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreKind {
+    /// 每个实例单独计数
+    #[default]
+    Memory,
+    /// 多个实例共用 `rate_limit_bucket` 表
+    Postgres,
+}
+
+/// 令牌桶容量与从空到满的时间，文本形式为 `60/1m`，时间单位可以是 s、m、h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota {
+            burst: 60,
+            period: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Quota {
+    /// 每秒补充的令牌数
+    pub fn rate(&self) -> f64 {
+        self.burst as f64 / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for Quota {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("invalid rate limit quota {:?}, expected e.g. 60/1m", s);
+        let (burst, period) = s.trim().split_once('/').ok_or_else(invalid)?;
+        let burst = burst.trim().parse::<u32>().map_err(|_| invalid())?;
+        let period = period.trim();
+        let (number, unit) = match period.find(|c: char| !c.is_ascii_digit()) {
+            Some(index) => period.split_at(index),
+            None => (period, "s"),
+        };
+        let number = number.parse::<u64>().map_err(|_| invalid())?;
+        let seconds = match unit {
+            "s" => number,
+            "m" => number * 60,
+            "h" => number * 3600,
+            _ => return Err(invalid()),
+        };
+        if burst == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(Quota {
+            burst,
+            period: Duration::from_secs(seconds),
+        })
+    }
+}
+
+impl Display for Quota {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}s", self.burst, self.period.as_secs())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    pub quota: Quota,
+    pub key: KeyKind,
+}
+
+/// 限流配置，从 `RATE_LIMIT_` 前缀的环境变量读取
+///
+/// `quota` 为默认配额，`RATE_LIMIT_ROUTES` 按路由模板单独设置，格式为 `路由=配额[@key]` 或 `路由=off`，
+/// 例如 `/countries=100/1m@api_key,/share/{token}=10/1s@token,/admin/logs=off`。
+/// 单独配置的路由各自计数，其余路由共用一个桶。
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub quota: String,
+    pub key: KeyKind,
+    pub routes: Option<String>,
+    pub api_key_header: String,
+    pub token_param: String,
+    /// 仅在前置代理会覆盖 `X-Forwarded-For`/`Forwarded` 时开启，否则调用方可以伪造 IP
+    pub trust_forwarded: bool,
+    pub store: StoreKind,
+    #[serde(skip)]
+    default_quota: Quota,
+    /// `None` 表示该路由不限流
+    #[serde(skip)]
+    rules: HashMap<String, Option<Rule>>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: false,
+            quota: "60/1m".to_string(),
+            key: KeyKind::Ip,
+            routes: None,
+            api_key_header: "x-api-key".to_string(),
+            token_param: "token".to_string(),
+            trust_forwarded: false,
+            store: StoreKind::Memory,
+            default_quota: Quota::default(),
+            rules: HashMap::new(),
+        }
+    }
+}
+
+static CURRENT: RwLock<Option<Arc<RateLimitConfig>>> = RwLock::new(None);
+
+impl RateLimitConfig {
+    /// 解析 `quota` 与 `routes`，格式错误时返回错误
+    pub fn parse(mut self) -> Result<Self, anyhow::Error> {
+        self.default_quota = self.quota.parse()?;
+        self.rules.clear();
+        if let Some(routes) = &self.routes {
+            for pair in routes.split(',').filter(|pair| !pair.trim().is_empty()) {
+                let (route, rule) = pair
+                    .rsplit_once('=')
+                    .ok_or(anyhow!("invalid RATE_LIMIT_ROUTES entry: {}", pair))?;
+                let rule = match rule.trim() {
+                    "off" => None,
+                    rule => {
+                        let (quota, key) = match rule.split_once('@') {
+                            Some((quota, key)) => (quota, key.trim().parse()?),
+                            None => (rule, self.key),
+                        };
+                        Some(Rule {
+                            quota: quota.parse()?,
+                            key,
+                        })
+                    }
+                };
+                self.rules.insert(route.trim().to_string(), rule);
+            }
+        }
+        Ok(self)
+    }
+
+    /// 路由模板对应的计数范围与规则，不限流时返回 `None`
+    pub fn rule<'a>(&self, route: &'a str) -> Option<(&'a str, Rule)> {
+        match self.rules.get(route) {
+            Some(rule) => rule.map(|rule| (route, rule)),
+            None if EXEMPT.contains(&route) => None,
+            None => Some((
+                "*",
+                Rule {
+                    quota: self.default_quota,
+                    key: self.key,
+                },
+            )),
+        }
+    }
+
+    /// 运行时使用的配置，热加载时替换
+    pub fn current() -> Arc<Self> {
+        CURRENT.read().unwrap_or_else(PoisonError::into_inner).clone().unwrap_or_default()
+    }
+
+    pub fn set(self) {
+        *CURRENT.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(self));
+    }
+}
+
+/// 一次取令牌的结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// 令牌补满的时间
+    pub reset: Duration,
+    /// 被拒绝时下一个令牌可用的时间
+    pub retry_after: Option<Duration>,
+}
+
+impl Decision {
+    fn new(quota: Quota, tokens: f64, allowed: bool) -> Self {
+        let rate = quota.rate();
+        Decision {
+            allowed,
+            limit: quota.burst,
+            remaining: tokens.max(0.0) as u32,
+            reset: Duration::from_secs_f64((quota.burst as f64 - tokens).max(0.0) / rate),
+            retry_after: (!allowed).then(|| Duration::from_secs_f64((1.0 - tokens).max(0.0) / rate)),
+        }
+    }
+
+    /// 秒数向上取整
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        let seconds = |duration: Duration| HeaderValue::from(duration.as_secs_f64().ceil() as u64);
+        headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(X_RATELIMIT_RESET, seconds(self.reset));
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(RETRY_AFTER, seconds(retry_after.max(Duration::from_secs(1))));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant,
+}
+
+/// 进程内的令牌桶
+#[derive(Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    takes: AtomicU64,
+}
+
+impl MemoryStore {
+    pub fn take(&self, key: &str, quota: Quota, now: Instant) -> Decision {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        //已补满的桶与新建的桶等价，定期清理
+        if self.takes.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+        let burst = quota.burst as f64;
+        let rate = quota.rate();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            full_at: now,
+        });
+        let tokens = (bucket.tokens + now.saturating_duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+        let allowed = tokens >= 1.0;
+        bucket.tokens = if allowed { tokens - 1.0 } else { tokens };
+        bucket.updated = now;
+        bucket.full_at = now + Duration::from_secs_f64((burst - bucket.tokens) / rate);
+        Decision::new(quota, bucket.tokens, allowed)
+    }
+
+    /// 查看是否还有令牌，不扣减
+    pub fn peek(&self, key: &str, quota: Quota, now: Instant) -> Decision {
+        let buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let burst = quota.burst as f64;
+        let tokens = match buckets.get(key) {
+            Some(bucket) => (bucket.tokens + now.saturating_duration_since(bucket.updated).as_secs_f64() * quota.rate()).min(burst),
+            None => burst,
+        };
+        Decision::new(quota, tokens, tokens >= 1.0)
+    }
+}
+
+/// 存放在 `rate_limit_bucket` 表中的令牌桶，多个实例共享
+pub struct PostgresStore {
+    conn: DatabaseConnection,
+    takes: AtomicU64,
+}
+
+impl PostgresStore {
+    pub async fn take(&self, key: &str, quota: Quota) -> Result<Decision, DbErr> {
+        if self.takes.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            self.conn
+                .execute(Statement::from_string(DatabaseBackend::Postgres, PRUNE_SQL))
+                .instrument(table_span("rate_limit_bucket", "delete", "delete idle rate_limit_bucket"))
+                .await?;
+        }
+        let row = self
+            .conn
+            .query_one(Statement::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                TAKE_SQL,
+                [key.into(), (quota.burst as f64).into(), quota.rate().into()],
+            ))
+            .instrument(table_span("rate_limit_bucket", "upsert", "take rate_limit_bucket token"))
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("rate limit bucket {}", key)))?;
+        let tokens: f64 = row.try_get("", "tokens")?;
+        let allowed: bool = row.try_get("", "allowed")?;
+        Ok(Decision::new(quota, tokens, allowed))
+    }
+
+    pub async fn peek(&self, key: &str, quota: Quota) -> Result<Decision, DbErr> {
+        let row = self
+            .conn
+            .query_one(Statement::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                PEEK_SQL,
+                [key.into(), (quota.burst as f64).into(), quota.rate().into()],
+            ))
+            .instrument(table_span("rate_limit_bucket", "select", "peek rate_limit_bucket tokens"))
+            .await?;
+        let tokens = match row {
+            Some(row) => row.try_get("", "tokens")?,
+            None => quota.burst as f64,
+        };
+        Ok(Decision::new(quota, tokens, tokens >= 1.0))
+    }
+}
+
+pub enum RateLimiter {
+    Memory(MemoryStore),
+    Postgres(PostgresStore),
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig, conn: &DatabaseConnection) -> Self {
+        match config.store {
+            StoreKind::Memory => RateLimiter::Memory(MemoryStore::default()),
+            StoreKind::Postgres => RateLimiter::Postgres(PostgresStore {
+                conn: conn.clone(),
+                takes: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub async fn take(&self, key: &str, quota: Quota) -> Result<Decision, DbErr> {
+        match self {
+            RateLimiter::Memory(store) => Ok(store.take(key, quota, Instant::now())),
+            RateLimiter::Postgres(store) => store.take(key, quota).await,
+        }
+    }
+
+    pub async fn peek(&self, key: &str, quota: Quota) -> Result<Decision, DbErr> {
+        match self {
+            RateLimiter::Memory(store) => Ok(store.peek(key, quota, Instant::now())),
+            RateLimiter::Postgres(store) => store.peek(key, quota).await,
+        }
+    }
+}
+
+/// 路由模板中指定名称的路径参数，如 `/share/{token}` 中的 `{token}`
+///
+/// 中间件在路由匹配之前执行，`match_info` 尚未填充，因此按路径段对齐取值
+fn path_param<'a>(pattern: &str, path: &'a str, name: &str) -> Option<&'a str> {
+    pattern
+        .split('/')
+        .zip(path.split('/'))
+        .find(|(segment, _)| {
+            segment
+                .strip_prefix('{')
+                .and_then(|segment| segment.strip_prefix(name))
+                .is_some_and(|rest| rest == "}" || rest.starts_with(':'))
+        })
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// 密钥类的值只保存摘要
+fn digest(value: &str) -> String {
+    hex::encode(&Sha256::digest(value.as_bytes())[..16])
+}
+
+fn client_key(req: &ServiceRequest, config: &RateLimitConfig, kind: KeyKind, route: &str) -> String {
+    let secret = match kind {
+        KeyKind::Ip => None,
+        KeyKind::ApiKey => req
+            .headers()
+            .get(config.api_key_header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| format!("api_key:{}", digest(value))),
+        KeyKind::Token => path_param(route, req.path(), &config.token_param).map(|value| format!("token:{}", digest(value))),
+    };
+    secret.unwrap_or_else(|| ip_key(req, config))
+}
+
+fn ip_key(req: &ServiceRequest, config: &RateLimitConfig) -> String {
+    let info = req.connection_info();
+    let ip = if config.trust_forwarded {
+        info.realip_remote_addr()
+    } else {
+        info.peer_addr()
+    };
+    format!("ip:{}", ip.unwrap_or("unknown"))
+}
+
+fn too_many_requests<B>(req: ServiceRequest, rule: Rule, route: &str, decision: Decision) -> ServiceResponse<EitherBody<B>> {
+    metrics().rate_limited.with_label_values(&[route]).inc();
+    let mut response = AppError::TooManyRequests(format!("rate limit of {} exceeded", rule.quota)).error_response();
+    decision.insert_headers(response.headers_mut());
+    req.into_response(response).map_into_right_body()
+}
+
+/// 限流中间件，需注册在 `trace_context::propagate` 内层，429 响应才会带上请求 id；
+/// 并注册在 `auth::authenticate` 外层，凭证无效的请求同样计数，暴力尝试在查库之前被拒绝
+///
+/// 按 API key 或路径中的 token 计数时，这些值在此处尚未校验，随意更换就能得到新的桶，
+/// 因此另按 IP 统计认证失败（401）的次数，用完配额后该 IP 的请求同样返回 429。
+///
+/// 共享存储不可用时放行请求并记录警告，不因限流影响服务可用性。
+pub async fn limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let config = RateLimitConfig::current();
+    let limiter = req.app_data::<Data<RateLimiter>>().cloned();
+    let Some(limiter) = limiter.filter(|_| config.enabled) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let route = req.match_pattern().unwrap_or_else(|| "default".to_string());
+    let Some((scope, rule)) = config.rule(&route) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let failures = (rule.key != KeyKind::Ip).then(|| format!("{} auth_failed {}", scope, ip_key(&req, &config)));
+    if let Some(failures) = &failures {
+        match limiter.peek(failures, rule.quota).await {
+            Ok(decision) if !decision.allowed => return Ok(too_many_requests(req, rule, &route, decision)),
+            Ok(_) => {}
+            Err(e) => warn!("rate limit store unavailable, failed authentications not checked: {}", e),
+        }
+    }
+    let key = format!("{} {}", scope, client_key(&req, &config, rule.key, &route));
+    let decision = match limiter.take(&key, rule.quota).await {
+        Ok(decision) => decision,
+        Err(e) => {
+            warn!("rate limit store unavailable, request allowed: {}", e);
+            return Ok(next.call(req).await?.map_into_left_body());
+        }
+    };
+    if !decision.allowed {
+        return Ok(too_many_requests(req, rule, &route, decision));
+    }
+    let mut response = next.call(req).await?;
+    if let Some(failures) = failures.filter(|_| response.status() == StatusCode::UNAUTHORIZED) {
+        if let Err(e) = limiter.take(&failures, rule.quota).await {
+            warn!("rate limit store unavailable, failed authentication not counted: {}", e);
+        }
+    }
+    decision.insert_headers(response.headers_mut());
+    Ok(response.map_into_left_body())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use actix_web::middleware::from_fn;
+    use actix_web::web::Data;
+    use actix_web::{get, App, HttpResponse};
+
+    use crate::auth::{authenticate, AuthConfig, Authenticator};
+    use crate::rate_limit::{limit, path_param, KeyKind, MemoryStore, Quota, RateLimitConfig, RateLimiter};
+
+    #[test]
+    fn test_parse_routes() -> Result<(), anyhow::Error> {
+        let quota: Quota = "100/1m".parse()?;
+        assert_eq!(quota.burst, 100);
+        assert_eq!(quota.period, Duration::from_secs(60));
+        assert!("0/1s".parse::<Quota>().is_err());
+        assert!("10/1d".parse::<Quota>().is_err());
+        let config = RateLimitConfig {
+            routes: Some("/countries=5/1s@api_key,/share/{token}=1/1h@token,/admin/logs=off".to_string()),
+            ..Default::default()
+        }
+        .parse()?;
+        let (scope, rule) = config.rule("/countries").unwrap();
+        assert_eq!(scope, "/countries");
+        assert_eq!(rule.key, KeyKind::ApiKey);
+        assert_eq!(rule.quota.burst, 5);
+        assert!(config.rule("/admin/logs").is_none());
+        assert!(config.rule("/healthz").is_none());
+        //未单独配置的路由共用默认配额
+        let (scope, rule) = config.rule("/countries/{id}").unwrap();
+        assert_eq!(scope, "*");
+        assert_eq!(rule.quota, Quota::default());
+        assert!(RateLimitConfig {
+            routes: Some("/countries=5/1s@user".to_string()),
+            ..Default::default()
+        }
+        .parse()
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_bucket() {
+        let store = MemoryStore::default();
+        let quota: Quota = "2/1s".parse().unwrap();
+        let start = Instant::now();
+        let first = store.take("a", quota, start);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        assert!(store.take("a", quota, start).allowed);
+        let denied = store.take("a", quota, start);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, Some(Duration::from_millis(500)));
+        assert_eq!(denied.reset, Duration::from_secs(1));
+        //其他调用方不受影响
+        assert!(store.take("b", quota, start).allowed);
+        assert!(store.take("a", quota, start + Duration::from_millis(500)).allowed);
+    }
+
+    #[test]
+    fn test_path_param() {
+        assert_eq!(path_param("/share/{token}", "/share/abc", "token"), Some("abc"));
+        assert_eq!(path_param("/share/{token:[a-z]+}/x", "/share/abc/x", "token"), Some("abc"));
+        assert_eq!(path_param("/share/{tokens}", "/share/abc", "token"), None);
+        assert_eq!(path_param("/countries/{id}", "/countries/1", "token"), None);
+    }
+
+    #[get("/limited")]
+    async fn limited() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_headers() {
+        RateLimitConfig {
+            enabled: true,
+            routes: Some("/limited=1/1m@api_key".to_string()),
+            ..Default::default()
+        }
+        .parse()
+        .unwrap()
+        .set();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(RateLimiter::Memory(MemoryStore::default())))
+                .wrap(from_fn(limit))
+                .service(limited),
+        ).await;
+        let request = |key: &str| {
+            actix_web::test::TestRequest::get()
+                .uri("/limited")
+                .insert_header(("x-api-key", key))
+                .to_request()
+        };
+        let response = actix_web::test::call_service(&app, request("k1")).await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers().get("x-ratelimit-limit").unwrap(), "1");
+        assert_eq!(response.headers().get("x-ratelimit-remaining").unwrap(), "0");
+        let response = actix_web::test::call_service(&app, request("k1")).await;
+        assert_eq!(response.status().as_u16(), 429);
+        assert_eq!(response.headers().get("retry-after").unwrap(), "60");
+        assert_eq!(response.headers().get("x-ratelimit-reset").unwrap(), "60");
+        let response = actix_web::test::call_service(&app, request("k2")).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_failed_authentication_limited() {
+        //与 test_middleware_headers 共用全局配置
+        RateLimitConfig {
+            enabled: true,
+            routes: Some("/limited=1/1m@api_key".to_string()),
+            ..Default::default()
+        }
+        .parse()
+        .unwrap()
+        .set();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(RateLimiter::Memory(MemoryStore::default())))
+                .app_data(Data::new(Authenticator::new(AuthConfig::default()).unwrap()))
+                .wrap(from_fn(authenticate))
+                .wrap(from_fn(limit))
+                .service(limited),
+        ).await;
+        let request = |key: &str| {
+            actix_web::test::TestRequest::get()
+                .uri("/limited")
+                .insert_header(("x-api-key", key))
+                .to_request()
+        };
+        let response = actix_web::test::call_service(&app, request("guess")).await;
+        assert_eq!(response.status().as_u16(), 401);
+        let response = actix_web::test::call_service(&app, request("guess")).await;
+        assert_eq!(response.status().as_u16(), 429);
+        //每次换一个 key 也会按 IP 被限流
+        for key in ["guess1", "guess2", "guess3"] {
+            let response = actix_web::test::call_service(&app, request(key)).await;
+            assert_eq!(response.status().as_u16(), 429);
+        }
+    }
+}
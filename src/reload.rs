@@ -0,0 +1,207 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::logging::LogReload;
+
+/// 可以在运行时生效的配置项，其余配置修改后需要重启
+pub const RELOADABLE: [&str; 13] = [
+    "log.stdout_level",
+    "log.file_level",
+    "log.syslog_level",
+    "log.journald_level",
+    "log.shipper_level",
+    "log.buffer_level",
+    "log.max_files",
+    "retry.max_retry",
+    "retry.delay",
+    "rate_limit.enabled",
+    "rate_limit.quota",
+    "rate_limit.key",
+    "rate_limit.routes",
+];
+
+/// 配置文件轮询间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// 已生效的配置项
+    pub applied: Vec<String>,
+    /// 修改后需要重启才能生效、本次忽略的配置项
+    pub rejected: Vec<String>,
+}
+
+/// 配置热加载：监听配置文件变化与 SIGHUP，重新应用可以运行时调整的配置
+pub struct Reloader {
+    current: Config,
+    log: LogReload,
+}
+
+impl Reloader {
+    pub fn new(current: Config, log: LogReload) -> Self {
+        Reloader { current, log }
+    }
+
+    /// 对比新旧配置，只应用 [`RELOADABLE`] 中的项
+    pub fn apply(&mut self, config: Config) -> Result<ReloadReport, anyhow::Error> {
+        let mut report = ReloadReport::default();
+        for key in changed_keys(&self.current, &config)? {
+            if RELOADABLE.contains(&key.as_str()) {
+                report.applied.push(key);
+            } else {
+                report.rejected.push(key);
+            }
+        }
+        if report.applied.is_empty() {
+            return Ok(report);
+        }
+        let mut rate_limit = self.current.rate_limit.clone();
+        rate_limit.enabled = config.rate_limit.enabled;
+        rate_limit.quota = config.rate_limit.quota;
+        rate_limit.key = config.rate_limit.key;
+        rate_limit.routes = config.rate_limit.routes;
+        let rate_limit = rate_limit.parse()?;
+        self.log.apply(&config.log)?;
+        config.retry.set();
+        if report.applied.iter().any(|key| key.starts_with("rate_limit.")) {
+            rate_limit.clone().set();
+        }
+        let current = &mut self.current;
+        current.log.stdout_level = config.log.stdout_level;
+        current.log.file_level = config.log.file_level;
+        current.log.syslog_level = config.log.syslog_level;
+        current.log.journald_level = config.log.journald_level;
+        current.log.shipper_level = config.log.shipper_level;
+        current.log.buffer_level = config.log.buffer_level;
+        current.log.max_files = config.log.max_files;
+        current.retry = config.retry;
+        current.rate_limit = rate_limit;
+        Ok(report)
+    }
+
+    fn reload(&mut self, trigger: &str) {
+        let report = Config::load().and_then(|config| self.apply(config));
+        match report {
+            Ok(report) => {
+                if !report.applied.is_empty() {
+                    info!(trigger, applied = ?report.applied, "configuration reloaded");
+                }
+                if !report.rejected.is_empty() {
+                    warn!(trigger, rejected = ?report.rejected, "configuration changes ignored, restart required");
+                }
+            }
+            Err(e) => warn!(trigger, "configuration reload failed, keeping current settings: {:#}", e),
+        }
+    }
+
+    /// 收到 SIGHUP 或配置文件修改时间变化时重新加载，直到任务被取消
+    pub async fn watch(mut self, file: Option<PathBuf>) -> Result<(), anyhow::Error> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let modified = |file: &Option<PathBuf>| -> Option<SystemTime> {
+            file.as_ref().and_then(|file| file.metadata().ok()).and_then(|metadata| metadata.modified().ok())
+        };
+        let mut last_modified = modified(&file);
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => self.reload("SIGHUP"),
+                _ = interval.tick() => {
+                    let current = modified(&file);
+                    if current != last_modified {
+                        last_modified = current;
+                        self.reload("config file");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 以 `section.key` 形式列出不同的配置项
+fn changed_keys(old: &Config, new: &Config) -> Result<Vec<String>, anyhow::Error> {
+    let old = toml::Value::try_from(old)?;
+    let new = toml::Value::try_from(new)?;
+    let (Some(old), Some(new)) = (old.as_table(), new.as_table()) else {
+        return Err(anyhow!("config must serialize to a table"));
+    };
+    let empty = toml::Table::new();
+    let mut keys = vec![];
+    for (section, value) in old {
+        let old_section = value.as_table().unwrap_or(&empty);
+        let new_section = new.get(section).and_then(|value| value.as_table()).unwrap_or(&empty);
+        let mut names: Vec<_> = old_section.keys().chain(new_section.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            if old_section.get(name) != new_section.get(name) {
+                keys.push(format!("{}.{}", section, name));
+            }
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod test {
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::{reload, Registry};
+
+    use crate::common::RetryPolicy;
+    use crate::config::Config;
+    use crate::logging::LogReload;
+    use crate::reload::{ReloadReport, Reloader};
+
+    #[test]
+    fn test_apply_reloadable_and_reject_structural() -> Result<(), anyhow::Error> {
+        let mut reloader = Reloader::new(Config::default(), LogReload::default());
+        let mut config = Config::default();
+        config.log.file_level = "debug".to_string();
+        config.retry.max_retry = 5;
+        config.server.bind = "127.0.0.1:9000".to_string();
+        config.telemetry.exporter_otlp_endpoint = Some("http://collector:4317".to_string());
+        let report = reloader.apply(config.clone())?;
+        assert_eq!(report, ReloadReport {
+            applied: vec!["log.file_level".to_string(), "retry.max_retry".to_string()],
+            rejected: vec!["server.bind".to_string(), "telemetry.exporter_otlp_endpoint".to_string()],
+        });
+        assert_eq!(RetryPolicy::current().max_retry, 5);
+        //结构性修改未生效，再次加载仍然提示
+        let report = reloader.apply(config)?;
+        assert!(report.applied.is_empty());
+        assert_eq!(report.rejected.len(), 2);
+        RetryPolicy::default().set();
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_level_rejected() {
+        let (_stdout_layer, stdout) = reload::Layer::<LevelFilter, Registry>::new(LevelFilter::INFO);
+        let (_file_layer, file) = reload::Layer::<LevelFilter, Registry>::new(LevelFilter::INFO);
+        let log = LogReload {
+            stdout: Some(stdout.clone()),
+            file: Some(file.clone()),
+            ..Default::default()
+        };
+        let mut reloader = Reloader::new(Config::default(), log);
+        let mut config = Config::default();
+        config.log.stdout_level = "loud".to_string();
+        assert!(reloader.apply(config).is_err());
+        //前面的级别有效、后面的无效时，前面的也不能生效
+        let mut config = Config::default();
+        config.log.stdout_level = "debug".to_string();
+        config.log.file_level = "loud".to_string();
+        assert!(reloader.apply(config).is_err());
+        assert_eq!(stdout.clone_current(), Some(LevelFilter::INFO));
+        assert_eq!(file.clone_current(), Some(LevelFilter::INFO));
+        //当前配置与实际生效的设置保持一致，之后的有效修改照常应用
+        let mut config = Config::default();
+        config.log.stdout_level = "debug".to_string();
+        assert!(reloader.apply(config).is_ok());
+        assert_eq!(stdout.clone_current(), Some(LevelFilter::DEBUG));
+    }
+}
@@ -1,20 +1,102 @@
-use actix_web::{get, HttpResponse, Responder};
-use actix_web::web::Data;
-use tracing::{info, info_span, instrument};
-use tracing_actix_web::RootSpan;
+use actix_web::{get, HttpResponse};
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use actix_web::middleware::from_fn;
+use actix_web::web::{Data, Query, ServiceConfig};
+use serde_json::json;
+use tracing::{info_span, instrument, Instrument};
+use std::time::Instant;
 use migration::sea_orm::{DatabaseConnection, TransactionTrait};
+use crate::auth;
+use crate::auth::Role;
+use crate::error::AppError;
+use crate::log_buffer::{LogBuffer, LogFilter, LogRecord};
+use crate::openapi::{Document, Operation};
+use crate::{api_key, country, health, openapi};
+use crate::metrics::metrics;
 use crate::store;
 
 #[get("/")]
 #[instrument(skip_all)]
-pub async fn index(conn: Data<DatabaseConnection>) -> impl Responder {
+pub async fn index(conn: Data<DatabaseConnection>) -> Result<HttpResponse, AppError> {
     let span = info_span!("-----");
-    let _a = span.enter();
-    let conn = &conn;
-    // root_span.field("admin_name");
-    let tx = conn.begin().await.unwrap();
-    store::incrment(&tx, 1).await.unwrap();
-    tx.commit().await.unwrap();
-    // root_span.record("admin_name", "lake");
-    HttpResponse::Ok().body("hello")
-}
\ No newline at end of file
+    async {
+        let start = Instant::now();
+        let tx = conn.begin().await?;
+        metrics().db_pool_wait.with_label_values(&["begin"]).observe(start.elapsed().as_secs_f64());
+        store::incrment(&tx, 1).await?;
+        tx.commit().await?;
+        Ok(HttpResponse::Ok().body("hello"))
+    }.instrument(span).await
+}
+
+/// Prometheus 文本格式的指标
+#[get("/metrics")]
+pub async fn prometheus_metrics(conn: Option<Data<DatabaseConnection>>) -> Result<HttpResponse, AppError> {
+    let text = metrics().render(conn.as_ref().map(|conn| conn.get_ref()))?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/plain; version=0.0.4"))
+        .body(text))
+}
+
+#[get("/admin/logs", wrap = "from_fn(auth::require_admin)")]
+pub async fn admin_logs(buffer: Data<LogBuffer>, filter: Query<LogFilter>) -> Result<HttpResponse, AppError> {
+    filter.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(buffer.recent(&filter)))
+}
+
+#[get("/admin/logs/stream", wrap = "from_fn(auth::require_admin)")]
+pub async fn admin_logs_stream(buffer: Data<LogBuffer>, filter: Query<LogFilter>) -> Result<HttpResponse, AppError> {
+    filter.validate().map_err(|e| AppError::Validation(e.to_string()))?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(buffer.sse_stream(filter.into_inner())))
+}
+
+/// 注册全部接口
+pub fn routes(cfg: &mut ServiceConfig) {
+    cfg.service(index)
+        .service(health::healthz)
+        .service(health::readyz)
+        .service(health::startupz)
+        .configure(country::configure)
+        .configure(api_key::configure)
+        .service(prometheus_metrics)
+        .service(admin_logs)
+        .service(admin_logs_stream)
+        .service(openapi::spec)
+        .service(openapi::docs);
+}
+
+pub fn openapi(doc: &mut Document) {
+    doc.route(
+        "get",
+        "/",
+        Operation::new("index", "示例：计数加一", "demo").content("200", "固定返回 hello", "text/plain"),
+    );
+    doc.route(
+        "get",
+        "/metrics",
+        Operation::new("metrics", "Prometheus 指标", "observability")
+            .content("200", "Prometheus 文本格式", "text/plain; version=0.0.4"),
+    );
+    let record = doc.schema::<LogRecord>();
+    doc.route(
+        "get",
+        "/admin/logs",
+        Operation::new("listLogs", "最近的日志", "admin")
+            .role(Role::Admin)
+            .query::<LogFilter>()
+            .response("200", "按时间顺序的日志", Some(json!({"type": "array", "items": record})))
+            .error("400", "查询参数不合法"),
+    );
+    doc.route(
+        "get",
+        "/admin/logs/stream",
+        Operation::new("streamLogs", "实时日志", "admin")
+            .role(Role::Admin)
+            .query::<LogFilter>()
+            .content("200", "每条日志一个 SSE 事件，data 为 LogRecord", "text/event-stream")
+            .error("400", "查询参数不合法"),
+    );
+}
@@ -0,0 +1,50 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::info;
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// 是否已开始停机
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Acquire)
+}
+
+/// 等待 SIGTERM 或 SIGINT，返回信号名并标记为停机中
+pub async fn signal_received() -> Result<&'static str, anyhow::Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let name = tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    };
+    SHUTTING_DOWN.store(true, Ordering::Release);
+    Ok(name)
+}
+
+/// 执行一个停机阶段并记录耗时
+pub async fn phase<F: Future>(name: &'static str, future: F) -> F::Output {
+    let start = Instant::now();
+    info!(phase = name, "shutdown phase started");
+    let output = future.await;
+    info!(phase = name, elapsed_ms = start.elapsed().as_millis() as u64, "shutdown phase finished");
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::shutdown::phase;
+
+    #[tokio::test]
+    async fn test_phase_returns_output() {
+        let output = phase("test", async {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            1
+        }).await;
+        assert_eq!(output, 1);
+    }
+}
@@ -1,19 +1,315 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use tracing::Span;
-use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use actix_web::web::Data;
+use actix_web::HttpMessage;
+use anyhow::anyhow;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::KeyValue;
+use tracing::field::Empty;
+use tracing::{warn, Span};
+use serde::{Deserialize, Serialize};
+use tracing_actix_web::{DefaultRootSpanBuilder, RequestId, RootSpanBuilder};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+use crate::error::AppError;
+use crate::metrics::metrics;
+use crate::telemetry;
+use crate::trace_context::TraceContext;
+
+pub const DEFAULT_SLOW_THRESHOLD_MS: u64 = 1000;
+
+/// 慢请求阈值，从 `SLOW_REQUEST_` 前缀的环境变量读取
+///
+/// `SLOW_REQUEST_ROUTES` 按路由模板单独设置，例如 `/=200,/countries/{id}=500`，单位毫秒
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SlowRequestConfig {
+    pub threshold_ms: u64,
+    pub routes: Option<String>,
+    #[serde(skip)]
+    thresholds: HashMap<String, Duration>,
+}
+
+impl Default for SlowRequestConfig {
+    fn default() -> Self {
+        SlowRequestConfig {
+            threshold_ms: DEFAULT_SLOW_THRESHOLD_MS,
+            routes: None,
+            thresholds: HashMap::new(),
+        }
+    }
+}
+
+impl SlowRequestConfig {
+    /// 解析 `routes`，格式错误时返回错误
+    pub fn parse(mut self) -> Result<Self, anyhow::Error> {
+        self.thresholds.clear();
+        if let Some(routes) = &self.routes {
+            for pair in routes.split(',').filter(|pair| !pair.trim().is_empty()) {
+                let (route, millis) = pair
+                    .rsplit_once('=')
+                    .ok_or(anyhow!("invalid SLOW_REQUEST_ROUTES entry: {}", pair))?;
+                let millis = millis
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| anyhow!("invalid SLOW_REQUEST_ROUTES threshold: {}", pair))?;
+                self.thresholds.insert(route.trim().to_string(), Duration::from_millis(millis));
+            }
+        }
+        Ok(self)
+    }
+
+    /// 路由模板对应的阈值，未单独配置时使用 `threshold_ms`
+    pub fn threshold(&self, route: &str) -> Duration {
+        self.thresholds
+            .get(route)
+            .copied()
+            .unwrap_or(Duration::from_millis(self.threshold_ms))
+    }
+}
+
+/// 从外到内列出错误链，非 [`AppError`] 只有一层
+pub fn error_chain(error: &actix_web::Error) -> Vec<String> {
+    match error.as_error::<AppError>() {
+        Some(error) => error.chain(),
+        None => vec![error.to_string()],
+    }
+}
+
+fn record_error_chain(span: &Span, error: &actix_web::Error) {
+    span.record("error.chain", error_chain(error).join(" -> ").as_str());
+    let db_error = error.as_error::<AppError>().and_then(AppError::db_error);
+    if let Some(db_error) = db_error {
+        span.record("error.db", tracing::field::debug(db_error));
+    }
+}
+
+/// 请求开始时间，存放在请求扩展中
+#[derive(Clone, Copy)]
+pub struct RequestStart(pub Instant);
 
 pub(crate) struct DomainRootSpanBuilder;
 
 impl RootSpanBuilder for DomainRootSpanBuilder {
     fn on_request_start(request: &ServiceRequest) -> Span {
-        let root_span = tracing_actix_web::root_span!(request);
+        //request_id 字段由 tracing-actix-web 生成，对外使用的 X-Request-Id 记录在 x_request_id，
+        //调用方未传入时沿用前者，两个字段相同
+        let mut context = TraceContext::from_headers_or(request.headers(), || {
+            request
+                .extensions()
+                .get::<RequestId>()
+                .map(ToString::to_string)
+                .unwrap_or_else(|| Uuid::new_v4().to_string())
+        });
+        let root_span = tracing_actix_web::root_span!(
+            request,
+            x_request_id = %context.request_id,
+            span_id = Empty,
+            parent_span_id = Empty,
+            tracestate = Empty,
+            //调用方身份由 auth::authenticate 中间件填充
+            user_id = Empty,
+            tenant = Empty,
+            auth_method = Empty,
+            roles = Empty,
+            //5xx 时记录完整错误链
+            error.chain = Empty,
+            error.db = Empty,
+        );
+        //启用 OTLP 导出时以导出的 span 为准，保证回写的 traceparent 与链路一致
+        let otel = root_span.context().span().span_context().clone();
+        if otel.is_valid() {
+            context.trace_id = format!("{:032x}", otel.trace_id());
+            context.span_id = format!("{:016x}", otel.span_id());
+            context.sampled = otel.is_sampled();
+        }
+        root_span.record("trace_id", context.trace_id.as_str());
+        root_span.record("span_id", context.span_id.as_str());
+        if let Some(parent_span_id) = &context.parent_span_id {
+            root_span.record("parent_span_id", parent_span_id.as_str());
+        }
+        if let Some(tracestate) = &context.tracestate {
+            root_span.record("tracestate", tracestate.as_str());
+        }
+        request.extensions_mut().insert(context);
+        request.extensions_mut().insert(RequestStart(Instant::now()));
         root_span
     }
     fn on_request_end<B: MessageBody>(
         span: Span,
         outcome: &Result<ServiceResponse<B>, actix_web::Error>,
     ) {
+        let error = match outcome {
+            Ok(response) if response.status().is_server_error() => response.response().error(),
+            Ok(_) => None,
+            Err(error) => Some(error),
+        };
+        if let Some(error) = error {
+            record_error_chain(&span, error);
+        }
+        if let Ok(response) = outcome {
+            let request = response.request();
+            let start = request.extensions().get::<RequestStart>().copied();
+            if let Some(RequestStart(start)) = start {
+                let elapsed = start.elapsed();
+                let route = request.match_pattern().unwrap_or_else(|| "default".to_string());
+                let status = response.status().as_u16();
+                let threshold = request
+                    .app_data::<Data<SlowRequestConfig>>()
+                    .map(|config| config.threshold(&route))
+                    .unwrap_or(Duration::from_millis(DEFAULT_SLOW_THRESHOLD_MS));
+                if elapsed > threshold {
+                    warn!(
+                        parent: &span,
+                        route = %route,
+                        status,
+                        elapsed_ms = elapsed.as_millis() as u64,
+                        threshold_ms = threshold.as_millis() as u64,
+                        "slow request"
+                    );
+                }
+                metrics().observe_request(&route, request.method().as_str(), status, elapsed);
+                telemetry::http_server_duration().record(
+                    elapsed.as_secs_f64(),
+                    &[
+                        KeyValue::new("http.route", route),
+                        KeyValue::new("http.request.method", request.method().to_string()),
+                        KeyValue::new("http.response.status_code", status as i64),
+                    ],
+                );
+            }
+        }
         DefaultRootSpanBuilder::on_request_end(span, outcome);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use actix_web::web::Data;
+    use actix_web::{get, App, HttpResponse};
+    use anyhow::Context as _;
+    use migration::sea_orm::DbErr;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Subscriber};
+    use tracing_actix_web::TracingLogger;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    use crate::error::AppError;
+    use crate::span::{DomainRootSpanBuilder, SlowRequestConfig};
+
+    type Fields = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// 收集 span 字段与事件字段
+    struct FieldLayer(Fields);
+
+    impl Visit for FieldLayer {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.lock().unwrap().push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for FieldLayer {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut FieldLayer(self.0.clone()));
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            values.record(&mut FieldLayer(self.0.clone()));
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            event.record(&mut FieldLayer(self.0.clone()));
+        }
+    }
+
+    fn value(fields: &Fields, name: &str) -> Option<String> {
+        fields.lock().unwrap().iter().rev().find(|(field, _)| field == name).map(|(_, value)| value.clone())
+    }
+
+    #[get("/slow")]
+    async fn slow() -> HttpResponse {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        HttpResponse::Ok().finish()
+    }
+
+    #[get("/fast")]
+    async fn fast() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[get("/broken")]
+    async fn broken() -> Result<HttpResponse, AppError> {
+        let error: Result<(), DbErr> = Err(DbErr::RecordNotUpdated);
+        error.context("increment test_table").context("handle /broken").map_err(AppError::from)?;
+        Ok(HttpResponse::Ok().finish())
+    }
+
+    #[test]
+    fn test_route_thresholds() -> Result<(), anyhow::Error> {
+        let config = SlowRequestConfig {
+            routes: Some("/=200, /countries/{id}=500".to_string()),
+            ..Default::default()
+        }.parse()?;
+        assert_eq!(config.threshold("/"), Duration::from_millis(200));
+        assert_eq!(config.threshold("/countries/{id}"), Duration::from_millis(500));
+        assert_eq!(config.threshold("/other"), Duration::from_millis(1000));
+        let invalid = SlowRequestConfig {
+            routes: Some("/=fast".to_string()),
+            ..Default::default()
+        };
+        assert!(invalid.parse().is_err());
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_slow_request_warning() -> Result<(), anyhow::Error> {
+        let fields: Fields = Arc::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(FieldLayer(fields.clone())));
+        let config = SlowRequestConfig {
+            threshold_ms: 10_000,
+            routes: Some("/slow=10".to_string()),
+            ..Default::default()
+        }.parse()?;
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(config))
+                .wrap(TracingLogger::<DomainRootSpanBuilder>::new())
+                .service(slow)
+                .service(fast),
+        ).await;
+        actix_web::test::call_service(&app, actix_web::test::TestRequest::get().uri("/fast").to_request()).await;
+        assert_eq!(value(&fields, "message"), None);
+        actix_web::test::call_service(&app, actix_web::test::TestRequest::get().uri("/slow").to_request()).await;
+        assert_eq!(value(&fields, "message").as_deref(), Some("slow request"));
+        assert_eq!(value(&fields, "route").as_deref(), Some("/slow"));
+        assert_eq!(value(&fields, "status").as_deref(), Some("200"));
+        assert_eq!(value(&fields, "threshold_ms").as_deref(), Some("10"));
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_server_error_chain_recorded() {
+        let fields: Fields = Arc::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(FieldLayer(fields.clone())));
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(TracingLogger::<DomainRootSpanBuilder>::new())
+                .service(broken),
+        ).await;
+        let response = actix_web::test::call_service(&app, actix_web::test::TestRequest::get().uri("/broken").to_request()).await;
+        assert_eq!(response.status().as_u16(), 500);
+        let chain = value(&fields, "error.chain").unwrap();
+        assert!(chain.starts_with("\"handle /broken -> increment test_table -> "), "{}", chain);
+        assert_eq!(value(&fields, "error.db").as_deref(), Some("RecordNotUpdated"));
+        let body: serde_json::Value = actix_web::test::read_body_json(response).await;
+        assert_eq!(body["code"], "internal_error");
+        assert_eq!(body["message"], "internal server error");
+    }
+}
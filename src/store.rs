@@ -1,66 +1,106 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::time::Duration;
-use anyhow::{anyhow, bail};
-use tracing::{info, info_span, instrument, Instrument, warn};
-use entity::{Country, CountryActiveModel};
-use migration::sea_orm::{ActiveModelTrait, DatabaseBackend, DatabaseTransaction, EntityTrait, Set, Statement};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use tracing::field::Empty;
+use tracing::{info, info_span, instrument, Instrument, Span, warn};
+use entity::{Country, CountryActiveModel, CountryCleanup, CountryCleanupActiveModel, CountryCleanupColumn, CountryColumn, CountryModel, RefCountEventActiveModel};
+use migration::sea_orm::{ActiveModelTrait, Condition, DatabaseBackend, DatabaseTransaction, DbErr, EntityTrait, IntoActiveModel, Order, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Select, Set, Statement};
+use migration::sea_orm::sea_query::{Expr, LikeExpr, OnConflict};
+use migration::sea_orm::{metric, ColumnTrait};
 use migration::{ConnectionTrait, Value};
+use crate::error::AppError;
+use crate::metrics::metrics;
+use crate::tasks;
+
+const INCREMENT_SQL: &str = r#"update "test_table" set "ref_count" = "test_table"."ref_count" + 1 , "v" = "test_table"."v" + 1 where "id" = $1 and "test_table"."v" = $2"#;
+const DECREMENT_SQL: &str = r#"update "test_table" set "ref_count" = "test_table"."ref_count" - 1 , "v" = "test_table"."v" + 1 where "id" = $1 and "test_table"."v" = $2"#;
+
+/// 数据库调用的 client span，随链路一起导出
+fn db_span(operation: &str, name: &str) -> Span {
+    table_span("test_table", operation, name)
+}
+
+/// `name` 为导出的 span 名称，`db.statement` 由 [`record_statement`] 在执行时填入
+pub(crate) fn table_span(table: &str, operation: &str, name: &str) -> Span {
+    info_span!(
+        "db.query",
+        otel.name = name,
+        otel.kind = "client",
+        db.system = "postgresql",
+        db.operation = operation,
+        db.sql.table = table,
+        db.statement = Empty,
+    )
+}
+
+/// 连接的 metric 回调，把实际执行的 SQL 记录到当前的数据库 span 上
+///
+/// 参数以 `$n` 占位，不含参数值
+pub fn record_statement(info: &metric::Info<'_>) {
+    Span::current().record("db.statement", info.statement.sql.as_str());
+}
 
 #[instrument(skip(tx))]
-pub async fn incrment(tx: &DatabaseTransaction, id: i64) -> Result<(), anyhow::Error> {
-    let info = Country::find_by_id(id).one(tx).await?;
-    let info = info.ok_or(anyhow!("data not found"))?;
+pub async fn incrment(tx: &DatabaseTransaction, id: i64) -> Result<(), AppError> {
+    let info = Country::find_by_id(id).one(tx).instrument(db_span("select", "find test_table by id")).await?;
+    let info = info.ok_or_else(|| AppError::NotFound(format!("test_table {} not found", id)))?;
     let rows = tx.execute(Statement::from_sql_and_values(
         DatabaseBackend::Postgres,
-        r#"update "test_table" set "ref_count" = "test_table"."ref_count" + 1 , "v" = "test_table"."v" + 1 where "id" = $1 and "test_table"."v" = $2"#,
+        INCREMENT_SQL,
         vec![Value::from(id), Value::from(info.v)],
-    )).await?;
+    )).instrument(db_span("update", "increment test_table by id and version")).await?;
     if rows.rows_affected() != 1 {
-        bail!("修改失败，影响行数：{}",rows.rows_affected())
+        metrics().optimistic_lock_conflicts.with_label_values(&["increment"]).inc();
+        return Err(AppError::VersionConflict(format!("修改失败，影响行数：{}", rows.rows_affected())));
     }
     let span = info_span!("async_test",id=id,aaa=tracing::field::Empty);
     async_test().instrument(span.clone()).await?;
     instrument_test().await?;
 
-    tokio::spawn(async move {
+    tasks::spawn("async_test_followup", async move {
         tokio::time::sleep(Duration::from_secs(1)).await;
-
-        let child = span.clone();
-        let _g = child.enter();
         warn!("警告");
-        child.record("aaa", "你好");
+        Span::current().record("aaa", "你好");
         info!("ok");
-    });
+    }.instrument(span));
 
     Ok(())
 }
 
 async fn async_test() -> Result<(), anyhow::Error> {
     info!("ok");
-    return Ok(());
+    Ok(())
 }
 
 #[instrument]
 async fn instrument_test() -> Result<(), anyhow::Error> {
     info!("ok");
-    return Ok(());
+    Ok(())
 }
 
 #[instrument(skip(tx))]
-pub async fn decrment(tx: &DatabaseTransaction, id: i64) -> Result<(), anyhow::Error> {
-    let info = Country::find_by_id(id).one(tx).await?;
-    let info = info.ok_or(anyhow!("data not found"))?;
+pub async fn decrment(tx: &DatabaseTransaction, id: i64) -> Result<(), AppError> {
+    let info = Country::find_by_id(id).one(tx).instrument(db_span("select", "find test_table by id")).await?;
+    let info = info.ok_or_else(|| AppError::NotFound(format!("test_table {} not found", id)))?;
+    if info.ref_count <= 0 {
+        return Err(AppError::Conflict(format!("test_table {} ref_count is already {}", id, info.ref_count)));
+    }
     let rows =
         tx.execute(Statement::from_sql_and_values(
             DatabaseBackend::Postgres,
-            r#"update "test_table" set "ref_count" = "test_table"."ref_count" - 1 , "v" = "test_table"."v" + 1 where "id" = $1 and "test_table"."v" = $2"#,
+            DECREMENT_SQL,
             vec![Value::from(id), Value::from(info.v)],
-        )).await?;
+        )).instrument(db_span("update", "decrement test_table by id and version")).await?;
     if rows.rows_affected() != 1 {
-        bail!("修改失败，影响行数：{}",rows.rows_affected())
+        metrics().optimistic_lock_conflicts.with_label_values(&["decrement"]).inc();
+        return Err(AppError::VersionConflict(format!("修改失败，影响行数：{}", rows.rows_affected())));
     }
     Ok(())
 }
 
+#[allow(unused_variables, unused_mut)]
 #[instrument(skip(tx))]
 pub async fn update_test(tx: &DatabaseTransaction) -> Result<(), anyhow::Error> {
     //如果修改找不到数据会异常
@@ -78,3 +118,593 @@ pub async fn update_test(tx: &DatabaseTransaction) -> Result<(), anyhow::Error>
     let res = model.update(tx).await?;
     Ok(())
 }
+
+pub async fn find_country<C: ConnectionTrait>(db: &C, id: i64) -> Result<CountryModel, AppError> {
+    Country::find_by_id(id)
+        .one(db)
+        .instrument(db_span("select", "find test_table by id"))
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("country {} not found", id)))
+}
+
+/// 列表查询条件，字段之间为 and 关系
+#[derive(Debug, Clone, Default)]
+pub struct CountryFilter {
+    pub name: Option<String>,
+    pub name_prefix: Option<String>,
+    /// 创建时间下限（包含）
+    pub created_from: Option<NaiveDateTime>,
+    /// 创建时间上限（不包含）
+    pub created_before: Option<NaiveDateTime>,
+    pub ref_count_min: Option<i64>,
+    pub ref_count_max: Option<i64>,
+}
+
+impl CountryFilter {
+    fn condition(&self) -> Condition {
+        let mut condition = Condition::all();
+        if let Some(name) = &self.name {
+            condition = condition.add(CountryColumn::Name.eq(name.as_str()));
+        }
+        if let Some(prefix) = &self.name_prefix {
+            //转义 like 通配符，前缀按字面匹配
+            let prefix = prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            condition = condition.add(Expr::col((Country, CountryColumn::Name)).like(LikeExpr::new(format!("{}%", prefix)).escape('\\')));
+        }
+        if let Some(from) = self.created_from {
+            condition = condition.add(CountryColumn::CreatedAt.gte(from));
+        }
+        if let Some(before) = self.created_before {
+            condition = condition.add(CountryColumn::CreatedAt.lt(before));
+        }
+        if let Some(min) = self.ref_count_min {
+            condition = condition.add(CountryColumn::RefCount.gte(min));
+        }
+        if let Some(max) = self.ref_count_max {
+            condition = condition.add(CountryColumn::RefCount.lte(max));
+        }
+        condition
+    }
+}
+
+/// 允许排序的字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountrySortField {
+    Id,
+    Name,
+    RefCount,
+    CreatedAt,
+}
+
+/// 排序方式，文本形式为字段名，前缀 `-` 表示降序，如 `-created_at`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CountrySort {
+    pub field: CountrySortField,
+    pub descending: bool,
+}
+
+impl CountrySort {
+    pub const ID: CountrySort = CountrySort { field: CountrySortField::Id, descending: false };
+    pub const CREATED_AT: CountrySort = CountrySort { field: CountrySortField::CreatedAt, descending: false };
+
+    /// keyset 分页只支持有索引的字段：主键与 `idx-country-created_at`
+    pub fn supports_keyset(&self) -> bool {
+        matches!(self.field, CountrySortField::Id | CountrySortField::CreatedAt)
+    }
+
+    fn column(&self) -> CountryColumn {
+        match self.field {
+            CountrySortField::Id => CountryColumn::Id,
+            CountrySortField::Name => CountryColumn::Name,
+            CountrySortField::RefCount => CountryColumn::RefCount,
+            CountrySortField::CreatedAt => CountryColumn::CreatedAt,
+        }
+    }
+
+    fn order(&self) -> Order {
+        if self.descending {
+            Order::Desc
+        } else {
+            Order::Asc
+        }
+    }
+}
+
+impl FromStr for CountrySort {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (descending, name) = match s.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, s),
+        };
+        let field = match name {
+            "id" => CountrySortField::Id,
+            "name" => CountrySortField::Name,
+            "ref_count" => CountrySortField::RefCount,
+            "created_at" => CountrySortField::CreatedAt,
+            _ => {
+                return Err(AppError::Validation(format!(
+                    "sort must be one of id, name, ref_count, created_at, optionally prefixed with '-', got {:?}",
+                    s
+                )))
+            }
+        };
+        Ok(CountrySort { field, descending })
+    }
+}
+
+impl Display for CountrySort {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self.field {
+            CountrySortField::Id => "id",
+            CountrySortField::Name => "name",
+            CountrySortField::RefCount => "ref_count",
+            CountrySortField::CreatedAt => "created_at",
+        };
+        if self.descending {
+            write!(f, "-{}", name)
+        } else {
+            f.write_str(name)
+        }
+    }
+}
+
+/// keyset 分页的位置：上一页最后一行的排序键，id 用于区分相同的创建时间
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountryCursor {
+    pub sort: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<NaiveDateTime>,
+    pub id: i64,
+}
+
+impl CountryCursor {
+    pub fn after(model: &CountryModel, sort: CountrySort) -> Self {
+        CountryCursor {
+            sort: sort.to_string(),
+            created_at: (sort.field == CountrySortField::CreatedAt).then_some(model.created_at),
+            id: model.id,
+        }
+    }
+
+    fn condition(&self, sort: CountrySort) -> Result<Condition, AppError> {
+        let invalid = || AppError::Validation("cursor does not match the requested sort".to_string());
+        if self.sort != sort.to_string() {
+            return Err(invalid());
+        }
+        let after = |column: CountryColumn, value: Value| {
+            if sort.descending {
+                column.lt(value)
+            } else {
+                column.gt(value)
+            }
+        };
+        match sort.field {
+            CountrySortField::Id => Ok(Condition::all().add(after(CountryColumn::Id, self.id.into()))),
+            CountrySortField::CreatedAt => {
+                let created_at = self.created_at.ok_or_else(invalid)?;
+                Ok(Condition::any()
+                    .add(after(CountryColumn::CreatedAt, created_at.into()))
+                    .add(
+                        Condition::all()
+                            .add(CountryColumn::CreatedAt.eq(created_at))
+                            .add(after(CountryColumn::Id, self.id.into())),
+                    ))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// 分页方式
+#[derive(Debug, Clone)]
+pub enum CountryPage {
+    Offset(u64),
+    /// `None` 为第一页
+    Keyset(Option<CountryCursor>),
+}
+
+/// 按条件查询一页数据，排序字段相同时按 id 排序保证结果稳定
+pub async fn list_countries<C: ConnectionTrait>(
+    db: &C,
+    filter: &CountryFilter,
+    sort: CountrySort,
+    page: &CountryPage,
+    limit: u64,
+) -> Result<Vec<CountryModel>, AppError> {
+    Ok(list_query(filter, sort, page, limit)?
+        .all(db)
+        .instrument(db_span("select", "list test_table"))
+        .await?)
+}
+
+fn list_query(filter: &CountryFilter, sort: CountrySort, page: &CountryPage, limit: u64) -> Result<Select<Country>, AppError> {
+    let mut query = Country::find().filter(filter.condition());
+    match page {
+        CountryPage::Offset(offset) => query = query.offset(*offset),
+        CountryPage::Keyset(cursor) => {
+            if !sort.supports_keyset() {
+                return Err(AppError::Validation(format!("keyset pagination does not support sort {}", sort)));
+            }
+            if let Some(cursor) = cursor {
+                query = query.filter(cursor.condition(sort)?);
+            }
+        }
+    }
+    query = query.order_by(sort.column(), sort.order());
+    if sort.field != CountrySortField::Id {
+        query = query.order_by(CountryColumn::Id, sort.order());
+    }
+    Ok(query.limit(limit))
+}
+
+pub async fn count_countries<C: ConnectionTrait>(db: &C, filter: &CountryFilter) -> Result<u64, AppError> {
+    Ok(Country::find()
+        .filter(filter.condition())
+        .count(db)
+        .instrument(db_span("select", "count test_table"))
+        .await?)
+}
+
+pub async fn create_country<C: ConnectionTrait>(db: &C, name: String, ref_count: i64) -> Result<CountryModel, AppError> {
+    let model = CountryActiveModel {
+        name: Set(name),
+        ref_count: Set(ref_count),
+        v: Set(0),
+        created_at: Set(chrono::Local::now().naive_local()),
+        ..Default::default()
+    };
+    Ok(model.insert(db).instrument(db_span("insert", "insert test_table")).await?)
+}
+
+/// 客户端期望的版本，来自 `If-Match`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectedVersion {
+    /// `If-Match: *`，只要求数据存在
+    Any,
+    /// 当前版本须为其中之一
+    OneOf(Vec<i64>),
+}
+
+impl ExpectedVersion {
+    pub fn matches(&self, version: i64) -> bool {
+        match self {
+            ExpectedVersion::Any => true,
+            ExpectedVersion::OneOf(versions) => versions.contains(&version),
+        }
+    }
+}
+
+/// 只修改传入的字段，版本号加一
+/// 修改名称与引用计数，引用计数的修改与 [`change_ref_count`] 一样处理归零
+pub async fn update_country(
+    tx: &DatabaseTransaction,
+    id: i64,
+    expected: &ExpectedVersion,
+    name: Option<String>,
+    ref_count: Option<i64>,
+    on_zero: ZeroAction,
+) -> Result<RefCountOutcome, AppError> {
+    let change = ref_count.map(RefCountChange::Assign);
+    update_with_ref_count(tx, id, expected, "update", name, change, on_zero).await
+}
+
+/// 在读取到的数据上修改并把版本号加一
+///
+/// 当前版本与 `expected` 不符时返回 [`AppError::PreconditionFailed`]。读取之后被其他请求修改过时，
+/// 指定了版本返回 [`AppError::PreconditionFailed`]，否则返回 [`AppError::VersionConflict`]
+async fn update_versioned<C: ConnectionTrait>(
+    db: &C,
+    current: CountryModel,
+    expected: &ExpectedVersion,
+    operation: &str,
+    apply: impl FnOnce(&mut CountryActiveModel),
+) -> Result<CountryModel, AppError> {
+    let id = current.id;
+    let version = current.v;
+    if !expected.matches(version) {
+        metrics().optimistic_lock_conflicts.with_label_values(&[operation]).inc();
+        return Err(AppError::PreconditionFailed(format!("country {} is at version {}", id, version)));
+    }
+    let mut model = current.into_active_model();
+    apply(&mut model);
+    model.v = Set(version + 1);
+    let updated = Country::update(model)
+        .filter(CountryColumn::V.eq(version))
+        .exec(db)
+        .instrument(db_span("update", "update test_table by id and version"))
+        .await;
+    match updated {
+        Err(DbErr::RecordNotUpdated) => {
+            metrics().optimistic_lock_conflicts.with_label_values(&[operation]).inc();
+            let message = format!("country {} was modified concurrently", id);
+            match expected {
+                ExpectedVersion::Any => Err(AppError::VersionConflict(message)),
+                ExpectedVersion::OneOf(_) => Err(AppError::PreconditionFailed(message)),
+            }
+        }
+        updated => Ok(updated?),
+    }
+}
+
+/// 引用计数归零后执行的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZeroAction {
+    /// 只记录归零事件
+    None,
+    /// 写入 `country_cleanup`，由清理任务处理；重新被引用时移除
+    #[default]
+    MarkForCleanup,
+    /// 在同一事务内删除
+    Delete,
+}
+
+impl Display for ZeroAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZeroAction::None => f.write_str("none"),
+            ZeroAction::MarkForCleanup => f.write_str("mark_for_cleanup"),
+            ZeroAction::Delete => f.write_str("delete"),
+        }
+    }
+}
+
+/// 引用计数配置，从 `REF_COUNT_` 前缀的环境变量读取
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RefCountConfig {
+    pub on_zero: ZeroAction,
+}
+
+/// 引用计数的修改
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefCountChange {
+    Increment(i64),
+    /// 不足时拒绝；`force` 时减到零为止
+    Decrement { amount: i64, force: bool },
+    /// 直接设为该值，来自 `PATCH`，调用方已校验不为负数
+    Assign(i64),
+}
+
+impl RefCountChange {
+    /// 修改后的值，不会小于零
+    pub fn apply(&self, ref_count: i64) -> Result<i64, AppError> {
+        match *self {
+            RefCountChange::Increment(amount) => ref_count
+                .checked_add(amount)
+                .ok_or_else(|| AppError::Validation(format!("ref_count {} + {} overflows", ref_count, amount))),
+            RefCountChange::Decrement { amount, force } => match ref_count.checked_sub(amount) {
+                Some(next) if next >= 0 => Ok(next),
+                _ if force => Ok(0),
+                _ => Err(AppError::Conflict(format!(
+                    "ref_count {} cannot be decremented by {} without force",
+                    ref_count, amount
+                ))),
+            },
+            RefCountChange::Assign(ref_count) => Ok(ref_count),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefCountOutcome {
+    pub country: CountryModel,
+    pub previous: i64,
+    /// 本次修改使引用计数归零时为执行的动作
+    pub zero_reached: Option<ZeroAction>,
+}
+
+/// 经过版本校验修改引用计数，归零时在同一事务内记录事件并执行 `on_zero`
+#[instrument(skip(tx, expected))]
+pub async fn change_ref_count(
+    tx: &DatabaseTransaction,
+    id: i64,
+    expected: &ExpectedVersion,
+    change: RefCountChange,
+    on_zero: ZeroAction,
+) -> Result<RefCountOutcome, AppError> {
+    update_with_ref_count(tx, id, expected, "ref_count", None, Some(change), on_zero).await
+}
+
+async fn update_with_ref_count(
+    tx: &DatabaseTransaction,
+    id: i64,
+    expected: &ExpectedVersion,
+    operation: &str,
+    name: Option<String>,
+    change: Option<RefCountChange>,
+    on_zero: ZeroAction,
+) -> Result<RefCountOutcome, AppError> {
+    let current = find_country(tx, id).await?;
+    let previous = current.ref_count;
+    let next = change.map(|change| change.apply(previous)).transpose()?;
+    let country = update_versioned(tx, current, expected, operation, |model| {
+        if let Some(name) = name {
+            model.name = Set(name);
+        }
+        if let Some(next) = next {
+            model.ref_count = Set(next);
+        }
+    })
+    .await?;
+    let next = next.unwrap_or(previous);
+    let now = chrono::Local::now().naive_local();
+    let zero_reached = if previous > 0 && next == 0 {
+        let (amount, forced) = match change {
+            Some(RefCountChange::Decrement { amount, force }) => (amount, force),
+            Some(RefCountChange::Increment(amount)) => (-amount, false),
+            Some(RefCountChange::Assign(_)) | None => (previous, false),
+        };
+        let event = RefCountEventActiveModel {
+            country_id: Set(id),
+            previous: Set(previous),
+            amount: Set(amount),
+            forced: Set(forced),
+            action: Set(on_zero.to_string()),
+            created_at: Set(now),
+            ..Default::default()
+        }
+        .insert(tx)
+        .instrument(table_span("ref_count_event", "insert", "insert ref_count_event"))
+        .await?;
+        match on_zero {
+            ZeroAction::None => {}
+            ZeroAction::MarkForCleanup => {
+                let mark = CountryCleanupActiveModel {
+                    country_id: Set(id),
+                    event_id: Set(event.id),
+                    marked_at: Set(now),
+                };
+                CountryCleanup::insert(mark)
+                    .on_conflict(
+                        OnConflict::column(CountryCleanupColumn::CountryId)
+                            .update_columns([CountryCleanupColumn::EventId, CountryCleanupColumn::MarkedAt])
+                            .to_owned(),
+                    )
+                    .exec_without_returning(tx)
+                    .instrument(table_span("country_cleanup", "insert", "upsert country_cleanup"))
+                    .await?;
+            }
+            ZeroAction::Delete => {
+                Country::delete_by_id(id)
+                    .exec(tx)
+                    .instrument(db_span("delete", "delete test_table by id"))
+                    .await?;
+            }
+        }
+        info!(previous, forced, action = %on_zero, "ref_count reached zero");
+        Some(on_zero)
+    } else {
+        if previous <= 0 && next > 0 {
+            //重新被引用，不再需要清理
+            CountryCleanup::delete_by_id(id)
+                .exec(tx)
+                .instrument(table_span("country_cleanup", "delete", "delete country_cleanup by country_id"))
+                .await?;
+        }
+        None
+    };
+    Ok(RefCountOutcome {
+        country,
+        previous,
+        zero_reached,
+    })
+}
+
+pub async fn delete_country<C: ConnectionTrait>(db: &C, id: i64, expected: &ExpectedVersion) -> Result<(), AppError> {
+    let mut delete = Country::delete_many().filter(CountryColumn::Id.eq(id));
+    if let ExpectedVersion::OneOf(versions) = expected {
+        delete = delete.filter(CountryColumn::V.is_in(versions.iter().copied()));
+    }
+    let result = delete
+        .exec(db)
+        .instrument(db_span("delete", "delete test_table by id and version"))
+        .await?;
+    if result.rows_affected == 0 {
+        //区分数据不存在与版本不符
+        let current = find_country(db, id).await?;
+        metrics().optimistic_lock_conflicts.with_label_values(&["delete"]).inc();
+        return Err(AppError::PreconditionFailed(format!("country {} is at version {}", id, current.v)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use entity::Country;
+    use migration::sea_orm::{metric, DatabaseBackend, EntityTrait, QueryTrait};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    use crate::error::AppError;
+    use crate::store::{db_span, list_query, record_statement, CountryCursor, CountryFilter, CountryPage, CountrySort, RefCountChange};
+
+    /// 收集 span 上记录的字段
+    struct FieldLayer(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Visit for FieldLayer {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.lock().unwrap().push((field.name().to_string(), value.to_string()));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.lock().unwrap().push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for FieldLayer {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut FieldLayer(self.0.clone()));
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            values.record(&mut FieldLayer(self.0.clone()));
+        }
+    }
+
+    #[test]
+    fn test_record_statement() {
+        let fields = Arc::new(Mutex::new(vec![]));
+        let subscriber = tracing_subscriber::registry().with(FieldLayer(fields.clone()));
+        let statement = Country::find_by_id(7).build(DatabaseBackend::Postgres);
+        tracing::subscriber::with_default(subscriber, || {
+            let _entered = db_span("select", "find test_table by id").entered();
+            record_statement(&metric::Info {
+                elapsed: Duration::ZERO,
+                statement: &statement,
+                failed: false,
+            });
+        });
+        let fields = fields.lock().unwrap();
+        let value = |name: &str| fields.iter().rev().find(|(field, _)| field == name).map(|(_, value)| value.clone());
+        assert_eq!(value("otel.name").as_deref(), Some("find test_table by id"));
+        assert_eq!(value("db.sql.table").as_deref(), Some("test_table"));
+        let sql = value("db.statement").unwrap_or_default();
+        assert!(sql.starts_with(r#"SELECT "test_table"."id""#), "{}", sql);
+        assert!(sql.ends_with(r#"WHERE "test_table"."id" = $1"#), "{}", sql);
+    }
+
+    #[test]
+    fn test_keyset_query() -> Result<(), anyhow::Error> {
+        let sort: CountrySort = "-created_at".parse()?;
+        let cursor = CountryCursor {
+            sort: sort.to_string(),
+            created_at: Some("2024-05-01T08:00:00".parse()?),
+            id: 7,
+        };
+        let filter = CountryFilter {
+            name_prefix: Some("10%_".to_string()),
+            ..Default::default()
+        };
+        let sql = list_query(&filter, sort, &CountryPage::Keyset(Some(cursor)), 21)?
+            .build(DatabaseBackend::Postgres)
+            .to_string();
+        assert!(sql.contains(r#""test_table"."name" LIKE E'10\\%\\_%' ESCAPE E'\\'"#), "{}", sql);
+        assert!(sql.contains(r#"("test_table"."created_at" < '2024-05-01 08:00:00' OR ("test_table"."created_at" = '2024-05-01 08:00:00' AND "test_table"."id" < 7))"#), "{}", sql);
+        assert!(sql.ends_with(r#"ORDER BY "test_table"."created_at" DESC, "test_table"."id" DESC LIMIT 21"#), "{}", sql);
+        //没有索引的字段不支持 keyset 分页
+        assert!(list_query(&filter, "name".parse()?, &CountryPage::Keyset(None), 21).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ref_count_floor() -> Result<(), anyhow::Error> {
+        assert_eq!(RefCountChange::Increment(2).apply(0)?, 2);
+        assert_eq!(RefCountChange::Decrement { amount: 2, force: false }.apply(2)?, 0);
+        let refused = RefCountChange::Decrement { amount: 3, force: false }.apply(2);
+        assert!(matches!(refused, Err(AppError::Conflict(_))));
+        //强制时减到零为止
+        assert_eq!(RefCountChange::Decrement { amount: 3, force: true }.apply(2)?, 0);
+        assert!(RefCountChange::Increment(1).apply(i64::MAX).is_err());
+        Ok(())
+    }
+}
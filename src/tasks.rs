@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info_span, warn, Instrument, Span};
+
+/// 后台任务登记表：跟踪在途任务，停机时等待或取消
+///
+/// 一般通过 [`spawn`] 使用进程级的 [`registry`]，测试中可单独创建
+#[derive(Clone, Default)]
+pub struct TaskRegistry {
+    tracker: TaskTracker,
+    cancel: CancellationToken,
+    in_flight: Arc<Mutex<HashMap<&'static str, usize>>>,
+}
+
+/// 任务结束（包括被取消或 panic）时递减计数
+struct InFlight {
+    name: &'static str,
+    counts: Arc<Mutex<HashMap<&'static str, usize>>>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(self.name) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(self.name);
+            }
+        }
+    }
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 在当前 span 下创建名为 `task` 的子 span 并运行 `future`，任务内的日志带上调用方的上下文
+    ///
+    /// 取消后 `future` 在下一个 await 点被丢弃，返回 `None`
+    pub fn spawn<F>(&self, name: &'static str, future: F) -> JoinHandle<Option<F::Output>>
+        where F: Future + Send + 'static,
+              F::Output: Send + 'static,
+    {
+        *self.in_flight.lock().unwrap().entry(name).or_default() += 1;
+        let guard = InFlight {
+            name,
+            counts: self.in_flight.clone(),
+        };
+        let span = info_span!(parent: Span::current(), "task", task.name = name);
+        let cancel = self.cancel.clone();
+        self.tracker.spawn(async move {
+            let _guard = guard;
+            tokio::select! {
+                output = future => Some(output),
+                _ = cancel.cancelled() => {
+                    warn!("task cancelled");
+                    None
+                }
+            }
+        }.instrument(span))
+    }
+
+    /// 在途任务总数
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().values().sum()
+    }
+
+    /// 按任务名统计的在途任务数
+    pub fn in_flight_by_name(&self) -> Vec<(&'static str, usize)> {
+        let mut counts: Vec<_> = self.in_flight.lock().unwrap().iter().map(|(name, count)| (*name, *count)).collect();
+        counts.sort();
+        counts
+    }
+
+    /// 等待在途任务结束，超过 `deadline` 后取消剩余任务
+    ///
+    /// 不会拒绝之后启动的任务：等待期间启动的任务同样会被等待，取消之后启动的任务随即被取消。
+    /// 返回被取消的任务数
+    pub async fn shutdown(&self, deadline: Duration) -> usize {
+        self.tracker.close();
+        if tokio::time::timeout(deadline, self.tracker.wait()).await.is_ok() {
+            return 0;
+        }
+        let remaining = self.in_flight();
+        self.cancel.cancel();
+        self.tracker.wait().await;
+        remaining
+    }
+}
+
+/// 进程级任务登记表
+pub fn registry() -> &'static TaskRegistry {
+    static REGISTRY: OnceLock<TaskRegistry> = OnceLock::new();
+    REGISTRY.get_or_init(TaskRegistry::new)
+}
+
+/// 使用进程级登记表启动后台任务，见 [`TaskRegistry::spawn`]
+pub fn spawn<F>(name: &'static str, future: F) -> JoinHandle<Option<F::Output>>
+    where F: Future + Send + 'static,
+          F::Output: Send + 'static,
+{
+    registry().spawn(name, future)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tracing::span::{Attributes, Id};
+    use tracing::{info, info_span, Event, Subscriber};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    use crate::tasks::TaskRegistry;
+
+    /// 记录每条事件所在的 span 链
+    struct ScopeLayer(Arc<Mutex<Vec<Vec<String>>>>);
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ScopeLayer {
+        fn on_new_span(&self, _: &Attributes<'_>, _: &Id, _: Context<'_, S>) {}
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let scope = ctx
+                .event_scope(event)
+                .map(|scope| scope.from_root().map(|span| span.name().to_string()).collect())
+                .unwrap_or_default();
+            self.0.lock().unwrap().push(scope);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawn_keeps_span() -> Result<(), anyhow::Error> {
+        let scopes = Arc::new(Mutex::new(vec![]));
+        let subscriber = tracing_subscriber::registry().with(ScopeLayer(scopes.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let registry = TaskRegistry::new();
+        let handle = info_span!("request").in_scope(|| {
+            registry.spawn("child", async {
+                info!("in task");
+                1
+            })
+        });
+        //set_default 只作用于当前线程，tokio::test 为单线程运行时，任务也在本线程执行
+        assert_eq!(handle.await?, Some(1));
+        assert_eq!(scopes.lock().unwrap().last().unwrap(), &vec!["request".to_string(), "task".to_string()]);
+        assert_eq!(registry.in_flight(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_then_cancels() -> Result<(), anyhow::Error> {
+        let registry = TaskRegistry::new();
+        let quick = registry.spawn("quick", tokio::time::sleep(Duration::from_millis(20)));
+        let stuck = registry.spawn("stuck", tokio::time::sleep(Duration::from_secs(60)));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.in_flight_by_name(), vec![("quick", 1), ("stuck", 1)]);
+        assert_eq!(registry.shutdown(Duration::from_millis(200)).await, 1);
+        assert_eq!(quick.await?, Some(()));
+        assert_eq!(stuck.await?, None);
+        assert_eq!(registry.in_flight(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use opentelemetry::metrics::Histogram;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{BatchConfigBuilder, BatchSpanProcessor, Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use serde::{Deserialize, Serialize};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::{Layer, Registry};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    #[default]
+    #[serde(rename = "grpc")]
+    Grpc,
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+}
+
+/// OTLP 导出配置，使用 OpenTelemetry 标准的 `OTEL_` 环境变量名
+///
+/// 未设置 `OTEL_EXPORTER_OTLP_ENDPOINT` 时不导出。
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub exporter_otlp_endpoint: Option<String>,
+    pub exporter_otlp_protocol: OtlpProtocol,
+    pub service_name: String,
+    /// `key=value,key2=value2`
+    pub resource_attributes: Option<String>,
+    /// 采样比例 0.0 ~ 1.0，上游已决定采样时沿用上游
+    pub traces_sampler_arg: f64,
+    pub bsp_max_queue_size: usize,
+    pub bsp_max_export_batch_size: usize,
+    /// 毫秒
+    pub bsp_schedule_delay: u64,
+    /// 毫秒
+    pub metric_export_interval: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            exporter_otlp_endpoint: None,
+            exporter_otlp_protocol: OtlpProtocol::Grpc,
+            service_name: env!("CARGO_PKG_NAME").to_string(),
+            resource_attributes: None,
+            traces_sampler_arg: 1.0,
+            bsp_max_queue_size: 2048,
+            bsp_max_export_batch_size: 512,
+            bsp_schedule_delay: 5000,
+            metric_export_interval: 60_000,
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(0.0..=1.0).contains(&self.traces_sampler_arg) {
+            bail!("OTEL_TRACES_SAMPLER_ARG must be between 0 and 1, got {}", self.traces_sampler_arg)
+        }
+        if self.bsp_max_export_batch_size > self.bsp_max_queue_size {
+            bail!("OTEL_BSP_MAX_EXPORT_BATCH_SIZE must not exceed OTEL_BSP_MAX_QUEUE_SIZE")
+        }
+        self.attributes()?;
+        Ok(())
+    }
+
+    fn attributes(&self) -> Result<Vec<KeyValue>, anyhow::Error> {
+        let mut attributes = vec![KeyValue::new("service.version", env!("CARGO_PKG_VERSION"))];
+        if let Some(value) = &self.resource_attributes {
+            for pair in value.split(',').filter(|pair| !pair.trim().is_empty()) {
+                let (key, value) = pair
+                    .split_once('=')
+                    .ok_or(anyhow!("invalid OTEL_RESOURCE_ATTRIBUTES entry: {}", pair))?;
+                attributes.push(KeyValue::new(key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        Ok(attributes)
+    }
+
+    fn resource(&self) -> Result<Resource, anyhow::Error> {
+        Ok(Resource::builder()
+            .with_service_name(self.service_name.clone())
+            .with_attributes(self.attributes()?)
+            .build())
+    }
+}
+
+/// drop 时刷新并关闭导出器
+pub struct TelemetryGuard {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+    //gRPC 通道的后台任务运行在独立的运行时上，避免关闭时与 actix 主线程互相等待
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Err(err) = self.tracer_provider.shutdown() {
+            eprintln!("Couldn't shut down tracer provider: {}", err);
+        }
+        if let Err(err) = self.meter_provider.shutdown() {
+            eprintln!("Couldn't shut down meter provider: {}", err);
+        }
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+pub type TelemetryLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// 创建 OTLP 导出器并注册为全局 provider，返回需加入订阅者的 tracing layer
+pub fn init(config: &TelemetryConfig) -> Result<Option<(TelemetryLayer, TelemetryGuard)>, anyhow::Error> {
+    let Some(endpoint) = config.exporter_otlp_endpoint.clone() else {
+        return Ok(None);
+    };
+    let config = config.clone();
+    //在独立线程中构建，避免在 async 上下文里创建阻塞客户端
+    let (tracer_provider, meter_provider, runtime) = std::thread::spawn(move || build(&config, &endpoint))
+        .join()
+        .map_err(|_| anyhow!("telemetry initialization panicked"))??;
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(tracer_provider.clone());
+    global::set_meter_provider(meter_provider.clone());
+
+    let tracer = tracer_provider.tracer(env!("CARGO_PKG_NAME"));
+    //导出链路本身（tonic/hyper/reqwest）产生的 span 不能再被导出
+    let filter = Targets::new()
+        .with_default(LevelFilter::INFO)
+        .with_target("h2", LevelFilter::OFF)
+        .with_target("hyper", LevelFilter::OFF)
+        .with_target("hyper_util", LevelFilter::OFF)
+        .with_target("tonic", LevelFilter::OFF)
+        .with_target("tower", LevelFilter::OFF)
+        .with_target("reqwest", LevelFilter::OFF)
+        .with_target("opentelemetry", LevelFilter::OFF)
+        .with_target("opentelemetry_sdk", LevelFilter::OFF)
+        .with_target("opentelemetry_otlp", LevelFilter::OFF);
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer).with_filter(filter).boxed();
+    Ok(Some((
+        layer,
+        TelemetryGuard {
+            tracer_provider,
+            meter_provider,
+            runtime,
+        },
+    )))
+}
+
+type Providers = (SdkTracerProvider, SdkMeterProvider, Option<tokio::runtime::Runtime>);
+
+fn build(config: &TelemetryConfig, endpoint: &str) -> Result<Providers, anyhow::Error> {
+    let resource = config.resource()?;
+    let (span_exporter, metric_exporter, runtime) = match config.exporter_otlp_protocol {
+        OtlpProtocol::Grpc => {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("otlp-grpc")
+                .enable_all()
+                .build()?;
+            let _enter = runtime.enter();
+            let spans = SpanExporter::builder().with_tonic().with_endpoint(endpoint).build()?;
+            let metrics = MetricExporter::builder().with_tonic().with_endpoint(endpoint).build()?;
+            (spans, metrics, Some(runtime))
+        }
+        OtlpProtocol::HttpProtobuf => {
+            let base = endpoint.trim_end_matches('/');
+            let spans = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", base))
+                .build()?;
+            let metrics = MetricExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/metrics", base))
+                .build()?;
+            (spans, metrics, None)
+        }
+    };
+
+    let batch = BatchConfigBuilder::default()
+        .with_max_queue_size(config.bsp_max_queue_size)
+        .with_max_export_batch_size(config.bsp_max_export_batch_size)
+        .with_scheduled_delay(Duration::from_millis(config.bsp_schedule_delay))
+        .build();
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_span_processor(BatchSpanProcessor::builder(span_exporter).with_batch_config(batch).build())
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.traces_sampler_arg))))
+        .with_resource(resource.clone())
+        .build();
+    let reader = PeriodicReader::builder(metric_exporter)
+        .with_interval(Duration::from_millis(config.metric_export_interval))
+        .build();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource)
+        .build();
+    Ok((tracer_provider, meter_provider, runtime))
+}
+
+/// `http.server.request.duration`（秒），未启用导出时为空操作
+pub fn http_server_duration() -> &'static Histogram<f64> {
+    static HISTOGRAM: OnceLock<Histogram<f64>> = OnceLock::new();
+    HISTOGRAM.get_or_init(|| {
+        global::meter(env!("CARGO_PKG_NAME"))
+            .f64_histogram("http.server.request.duration")
+            .with_unit("s")
+            .build()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::web::{Bytes, Data};
+    use actix_web::{post, App, HttpResponse, HttpServer};
+    use opentelemetry::KeyValue;
+    use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{TraceService, TraceServiceServer};
+    use opentelemetry_proto::tonic::collector::trace::v1::{ExportTraceServiceRequest, ExportTraceServiceResponse};
+    use prost::Message;
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::telemetry::{build, OtlpProtocol, TelemetryConfig};
+    use opentelemetry::trace::TracerProvider;
+
+    type Received = Arc<Mutex<Vec<ExportTraceServiceRequest>>>;
+
+    fn span_names(received: &Received) -> Vec<String> {
+        received
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|request| request.resource_spans.iter())
+            .flat_map(|resource| resource.scope_spans.iter())
+            .flat_map(|scope| scope.spans.iter())
+            .map(|span| span.name.clone())
+            .collect()
+    }
+
+    fn service_name(received: &Received) -> Option<String> {
+        let requests = received.lock().unwrap();
+        let resource = requests.first()?.resource_spans.first()?.resource.as_ref()?;
+        resource
+            .attributes
+            .iter()
+            .find(|kv| kv.key == "service.name")
+            .and_then(|kv| kv.value.as_ref())
+            .map(|value| format!("{:?}", value.value))
+    }
+
+    fn config(protocol: OtlpProtocol, endpoint: String) -> TelemetryConfig {
+        TelemetryConfig {
+            exporter_otlp_endpoint: Some(endpoint),
+            exporter_otlp_protocol: protocol,
+            service_name: "collector-test".to_string(),
+            resource_attributes: Some("deployment.environment=test".to_string()),
+            bsp_schedule_delay: 50,
+            ..Default::default()
+        }
+    }
+
+    /// 在当前线程的订阅者下产生一个 span 并强制导出
+    fn emit(config: &TelemetryConfig) -> Result<(), anyhow::Error> {
+        let (tracer_provider, meter_provider, runtime) = build(config, config.exporter_otlp_endpoint.as_deref().unwrap())?;
+        let layer = tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("test"));
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("exported", id = 1);
+            let _enter = span.enter();
+        });
+        tracer_provider.force_flush()?;
+        tracer_provider.shutdown()?;
+        let _ = meter_provider.shutdown();
+        if let Some(runtime) = runtime {
+            runtime.shutdown_background();
+        }
+        Ok(())
+    }
+
+    #[post("/v1/traces")]
+    async fn http_traces(received: Data<Received>, body: Bytes) -> HttpResponse {
+        received.lock().unwrap().push(ExportTraceServiceRequest::decode(body).unwrap());
+        HttpResponse::Ok().content_type("application/x-protobuf").body(ExportTraceServiceResponse::default().encode_to_vec())
+    }
+
+    #[actix_web::test]
+    async fn test_export_over_http() -> Result<(), anyhow::Error> {
+        let received: Received = Arc::default();
+        let data = Data::new(received.clone());
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = HttpServer::new(move || App::new().app_data(data.clone()).service(http_traces))
+            .workers(1)
+            .listen(listener)?
+            .run();
+        actix_web::rt::spawn(server);
+
+        let config = config(OtlpProtocol::HttpProtobuf, format!("http://{}", addr));
+        actix_web::rt::task::spawn_blocking(move || emit(&config)).await??;
+        assert_eq!(span_names(&received), vec!["exported"]);
+        assert_eq!(service_name(&received).as_deref(), Some("Some(StringValue(\"collector-test\"))"));
+        Ok(())
+    }
+
+    struct Collector(Received);
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            self.0.lock().unwrap().push(request.into_inner());
+            Ok(tonic::Response::new(ExportTraceServiceResponse::default()))
+        }
+    }
+
+    #[test]
+    fn test_export_over_grpc() -> Result<(), anyhow::Error> {
+        let received: Received = Arc::default();
+        let runtime = tokio::runtime::Runtime::new()?;
+        let listener = runtime.block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))?;
+        let addr = listener.local_addr()?;
+        let service = TraceServiceServer::new(Collector(received.clone()));
+        runtime.spawn(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        emit(&config(OtlpProtocol::Grpc, format!("http://{}", addr)))?;
+        assert_eq!(span_names(&received), vec!["exported"]);
+        runtime.shutdown_background();
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = TelemetryConfig {
+            traces_sampler_arg: 1.5,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        config.traces_sampler_arg = 0.5;
+        config.resource_attributes = Some("broken".to_string());
+        assert!(config.validate().is_err());
+        config.resource_attributes = Some("a=b, c=d".to_string());
+        let attributes = config.attributes().unwrap();
+        assert!(attributes.contains(&KeyValue::new("c", "d")));
+        assert!(attributes.contains(&KeyValue::new("service.version", env!("CARGO_PKG_VERSION"))));
+    }
+}
@@ -0,0 +1,330 @@
+use std::future::{ready, Ready};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::error::InternalError;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use uuid::Uuid;
+
+pub const REQUEST_ID: &str = "x-request-id";
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// 请求级追踪上下文：`X-Request-Id` 与 W3C Trace Context
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 关联请求使用的唯一 id：回写到 `X-Request-Id`、错误响应体的 `request_id` 与日志的 `x_request_id` 字段。
+    /// 调用方未传入时与 tracing-actix-web 生成的 `request_id` 相同
+    pub request_id: String,
+    pub trace_id: String,
+    /// 本服务处理该请求的 span id，向下游传播时作为 parent
+    pub span_id: String,
+    /// 上游传入的 parent id，没有 traceparent 时为空
+    pub parent_span_id: Option<String>,
+    pub sampled: bool,
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// 读取请求头，缺失或格式非法时生成新的 id
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self::from_headers_or(headers, || Uuid::new_v4().to_string())
+    }
+
+    /// 同 [`from_headers`](Self::from_headers)，缺少 `X-Request-Id` 时使用 `request_id` 生成的 id
+    pub fn from_headers_or(headers: &HeaderMap, request_id: impl FnOnce() -> String) -> Self {
+        let request_id = header(headers, REQUEST_ID)
+            .filter(|id| valid_request_id(id))
+            .map(str::to_string)
+            .unwrap_or_else(request_id);
+        let parent = header(headers, TRACEPARENT).and_then(parse_traceparent);
+        let tracestate = parent
+            .as_ref()
+            .and_then(|_| header(headers, TRACESTATE))
+            .map(str::to_string);
+        let (trace_id, parent_span_id, sampled) = match parent {
+            Some((trace_id, parent_id, sampled)) => (trace_id, Some(parent_id), sampled),
+            None => (Uuid::new_v4().simple().to_string(), None, true),
+        };
+        TraceContext {
+            request_id,
+            trace_id,
+            span_id: new_span_id(),
+            parent_span_id,
+            sampled,
+            tracestate,
+        }
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{}", self.trace_id, self.span_id, if self.sampled { "01" } else { "00" })
+    }
+
+    /// 给下游请求带上追踪头，使链路可以跨服务延续
+    pub fn inject(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let builder = builder
+            .header(REQUEST_ID, &self.request_id)
+            .header(TRACEPARENT, self.traceparent());
+        match &self.tracestate {
+            Some(state) => builder.header(TRACESTATE, state),
+            None => builder,
+        }
+    }
+
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.request_id) {
+            headers.insert(HeaderName::from_static(REQUEST_ID), value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&self.traceparent()) {
+            headers.insert(HeaderName::from_static(TRACEPARENT), value);
+        }
+        if let Some(value) = self.tracestate.as_ref().and_then(|state| HeaderValue::from_str(state).ok()) {
+            headers.insert(HeaderName::from_static(TRACESTATE), value);
+        }
+    }
+
+    /// 当前请求的上下文，只在请求处理的 future 内可用（`tokio::spawn` 出去的任务需自行传递）
+    pub fn current() -> Option<TraceContext> {
+        CURRENT.try_with(|context| context.clone()).ok()
+    }
+}
+
+impl FromRequest for TraceContext {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let context = req
+            .extensions()
+            .get::<TraceContext>()
+            .cloned()
+            .unwrap_or_else(|| TraceContext::from_headers(req.headers()));
+        ready(Ok(context))
+    }
+}
+
+/// 中间件：在响应头中回写 `X-Request-Id` / `traceparent`，并在处理期间提供 [`TraceContext::current`]
+///
+/// 需注册在 `TracingLogger` 内层（先 `wrap` 本中间件，再 `wrap` `TracingLogger`）。
+/// 内层返回的错误在此转换为响应，错误响应同样带有这些响应头。
+pub async fn propagate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let context = req.extensions().get::<TraceContext>().cloned();
+    let context = context.unwrap_or_else(|| {
+        let context = TraceContext::from_headers(req.headers());
+        req.extensions_mut().insert(context.clone());
+        context
+    });
+    //在上下文内生成错误响应，响应体才能带上 request_id
+    let result = CURRENT
+        .scope(context.clone(), async move {
+            next.call(req).await.map_err(|error| {
+                let response = error.error_response();
+                (error, response)
+            })
+        })
+        .await;
+    match result {
+        Ok(mut response) => {
+            context.insert_headers(response.headers_mut());
+            Ok(response)
+        }
+        Err((error, mut response)) => {
+            context.insert_headers(response.headers_mut());
+            Err(InternalError::from_response(error, response).into())
+        }
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim)
+}
+
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn new_span_id() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// 解析 `version-trace_id-parent_id-flags`，返回 (trace_id, parent_id, sampled)
+fn parse_traceparent(value: &str) -> Option<(String, String, bool)> {
+    let mut parts = value.split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+    if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    if !is_hex(trace_id, 32) || trace_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    if !is_hex(parent_id, 16) || parent_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    if !is_hex(flags, 2) {
+        return None;
+    }
+    let sampled = u8::from_str_radix(flags, 16).ok()? & 0x01 == 1;
+    Some((trace_id.to_string(), parent_id.to_string(), sampled))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::http::header::HeaderMap;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+    use actix_web::body::{to_bytes, BoxBody, MessageBody};
+    use actix_web::dev::{ServiceRequest, ServiceResponse};
+    use actix_web::middleware::{from_fn, Next};
+    use actix_web::{get, App, HttpResponse};
+    use tracing_actix_web::{RequestId, TracingLogger};
+
+    use crate::error::AppError;
+    use crate::span::DomainRootSpanBuilder;
+    use crate::trace_context::{parse_traceparent, propagate, TraceContext};
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn headers(values: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_parse_traceparent() {
+        assert_eq!(
+            parse_traceparent(PARENT),
+            Some(("4bf92f3577b34da6a3ce929d0e0e4736".to_string(), "00f067aa0ba902b7".to_string(), true))
+        );
+        assert!(parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("garbage").is_none());
+    }
+
+    #[test]
+    fn test_context_continues_incoming_trace() {
+        let context = TraceContext::from_headers(&headers(&[
+            ("x-request-id", "abc-123"),
+            ("traceparent", PARENT),
+            ("tracestate", "vendor=1"),
+        ]));
+        assert_eq!(context.request_id, "abc-123");
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_ne!(context.span_id, "00f067aa0ba902b7");
+        assert_eq!(context.tracestate.as_deref(), Some("vendor=1"));
+        assert!(context.traceparent().starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+    }
+
+    #[test]
+    fn test_context_generated_when_missing() {
+        let context = TraceContext::from_headers(&headers(&[("tracestate", "vendor=1")]));
+        assert_eq!(context.request_id.len(), 36);
+        assert_eq!(context.trace_id.len(), 32);
+        assert_eq!(context.span_id.len(), 16);
+        assert!(context.parent_span_id.is_none());
+        //没有合法的 traceparent 时 tracestate 必须丢弃
+        assert!(context.tracestate.is_none());
+    }
+
+    #[test]
+    fn test_inject_outbound_headers() -> Result<(), anyhow::Error> {
+        let context = TraceContext::from_headers(&headers(&[("x-request-id", "abc-123"), ("traceparent", PARENT)]));
+        let request = context.inject(reqwest::Client::new().get("http://localhost/")).build()?;
+        assert_eq!(request.headers()["x-request-id"], "abc-123");
+        assert_eq!(request.headers()["traceparent"], context.traceparent().as_str());
+        Ok(())
+    }
+
+    #[get("/context")]
+    async fn echo_context(context: TraceContext) -> HttpResponse {
+        assert_eq!(TraceContext::current(), Some(context.clone()));
+        HttpResponse::Ok().body(context.trace_id)
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_echoes_headers() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(from_fn(propagate))
+                .wrap(TracingLogger::<DomainRootSpanBuilder>::new())
+                .service(echo_context),
+        ).await;
+        let request = actix_web::test::TestRequest::get()
+            .uri("/context")
+            .insert_header(("x-request-id", "abc-123"))
+            .insert_header(("traceparent", PARENT))
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.headers().get("x-request-id").unwrap(), "abc-123");
+        let traceparent = response.headers().get("traceparent").unwrap().to_str().unwrap().to_string();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        let body = actix_web::test::read_body(response).await;
+        assert_eq!(body, "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    async fn reject(_: ServiceRequest, _: Next<impl MessageBody>) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+        Err(AppError::NotFound("country 7 not found".to_string()).into())
+    }
+
+    #[get("/request-id")]
+    async fn echo_request_id(context: TraceContext, request_id: RequestId) -> HttpResponse {
+        assert_eq!(context.request_id, request_id.to_string());
+        HttpResponse::Ok().body(context.request_id)
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_echoes_headers_on_error() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(from_fn(reject))
+                .wrap(from_fn(propagate))
+                .wrap(TracingLogger::<DomainRootSpanBuilder>::new())
+                .service(echo_context),
+        ).await;
+        let request = actix_web::test::TestRequest::get().uri("/context").insert_header(("traceparent", PARENT)).to_request();
+        //错误继续向外传递，由 actix 使用其中保存的响应
+        let Err(error) = actix_web::test::try_call_service(&app, request).await else {
+            panic!("expected the error to propagate");
+        };
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 404);
+        let request_id = response.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+        assert!(response.headers().get("traceparent").is_some());
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["request_id"], request_id.as_str());
+    }
+
+    #[actix_web::test]
+    async fn test_generated_request_id_matches_logs() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(from_fn(propagate))
+                .wrap(TracingLogger::<DomainRootSpanBuilder>::new())
+                .service(echo_request_id),
+        ).await;
+        let request = actix_web::test::TestRequest::get().uri("/request-id").to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        let request_id = response.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+        let body = actix_web::test::read_body(response).await;
+        assert_eq!(body, request_id);
+    }
+}